@@ -0,0 +1,2 @@
+ALTER TABLE files DROP COLUMN password_salt;
+ALTER TABLE files DROP COLUMN password_hash;
@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN password_hash BLOB;
+ALTER TABLE files ADD COLUMN password_salt TEXT;
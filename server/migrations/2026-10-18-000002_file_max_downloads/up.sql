@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN max_downloads INTEGER;
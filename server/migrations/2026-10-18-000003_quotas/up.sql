@@ -0,0 +1,6 @@
+CREATE TABLE quotas (
+    user_id         INTEGER NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    max_bytes       BIGINT,
+    max_files       INTEGER,
+    max_file_bytes  BIGINT
+);
@@ -0,0 +1,5 @@
+-- Sessions from before this migration count as expired.
+ALTER TABLE sessions ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE sessions ADD COLUMN last_seen TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE sessions ADD COLUMN user_agent TEXT;
+ALTER TABLE sessions ADD COLUMN ip TEXT;
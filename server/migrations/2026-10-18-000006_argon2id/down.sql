@@ -0,0 +1,16 @@
+-- Share links locked since the upgrade only have a PHC string. Give them
+-- an argon2i hash no password produces, so they stay locked.
+UPDATE files SET password_hash = zeroblob(1), password_salt = ''
+    WHERE password_phc IS NOT NULL AND password_hash IS NULL;
+ALTER TABLE files DROP COLUMN password_phc;
+
+-- Accounts that only have a PHC string can't be represented anymore.
+CREATE TABLE passwords_old (
+    user_id         INTEGER NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    hash            BLOB NOT NULL,
+    salt            TEXT NOT NULL
+);
+INSERT INTO passwords_old (user_id, hash, salt)
+    SELECT user_id, hash, salt FROM passwords WHERE hash IS NOT NULL AND salt IS NOT NULL;
+DROP TABLE passwords;
+ALTER TABLE passwords_old RENAME TO passwords;
@@ -0,0 +1,13 @@
+-- Passwords become argon2id PHC strings. The argon2i hash and salt columns
+-- stay for accounts and share links that haven't been unlocked since.
+CREATE TABLE passwords_new (
+    user_id         INTEGER NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    hash            BLOB,
+    salt            TEXT,
+    phc             TEXT
+);
+INSERT INTO passwords_new (user_id, hash, salt) SELECT user_id, hash, salt FROM passwords;
+DROP TABLE passwords;
+ALTER TABLE passwords_new RENAME TO passwords;
+
+ALTER TABLE files ADD COLUMN password_phc TEXT;
@@ -0,0 +1,8 @@
+CREATE TABLE lockouts (
+    id              INTEGER NOT NULL PRIMARY KEY,
+    kind            TEXT NOT NULL,
+    subject         TEXT NOT NULL,
+    failures        INTEGER NOT NULL,
+    created_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_until    TIMESTAMP NOT NULL
+);
@@ -1,9 +1,18 @@
+use crate::api::{BaseUrl, ClientIp};
+use crate::config;
 use crate::db::{self, schema::*};
-use crate::model::{File, FileInsert, User};
+use crate::model::{File, FileInsert, FileLock, Quota, User};
+use crate::storage::{SharedStorage, Storage};
+use crate::task::Thumbnailer;
 use crate::util::{
-    download::FileDownload,
+    download::{FileDownload, Lookup, RangeHeaders, Ranges},
     error::{Error as E, Res},
+    filename, metrics, mime,
     random::random_ascii,
+    secret::Secret,
+    throttle::Throttle,
+    thumbnail,
+    unlock::{SharePassword, UnlockForm},
     upload::FileUpload,
 };
 
@@ -11,26 +20,30 @@ use chrono::prelude::*;
 use diesel::prelude::*;
 use rocket::{
     http::Status,
+    request::Form,
     response::{status, NamedFile},
+    State,
 };
-use rocket_contrib::json::JsonValue;
+use rocket_contrib::json::{Json, JsonValue};
 use std::path::{Path, PathBuf};
 
-const FLINK_DIR: &'static str = env!("FLINK_DIR");
 const STORE_DIR: &'static str = "store";
 const SPOOL_DIR: &'static str = "spool";
-const FILE_KEY_LEN: usize = 32;
+
+pub fn store_dir() -> PathBuf {
+    config::get().dir.join(STORE_DIR)
+}
+
+pub fn spool_dir() -> PathBuf {
+    config::get().dir.join(SPOOL_DIR)
+}
 
 pub fn user_store(user_id: i32) -> PathBuf {
-    Path::new(FLINK_DIR)
-        .join(STORE_DIR)
-        .join(user_id.to_string())
+    store_dir().join(user_id.to_string())
 }
 
 pub fn user_spool(user_id: i32) -> PathBuf {
-    Path::new(FLINK_DIR)
-        .join(SPOOL_DIR)
-        .join(user_id.to_string())
+    spool_dir().join(user_id.to_string())
 }
 
 pub fn user_store_file(user_id: i32, key: &str) -> PathBuf {
@@ -43,75 +56,175 @@ pub enum FileError {
     PermissionDenied { file: File, user: User },
     #[fail(display = "invalid duration: {}", string)]
     InvalidDuration { string: String },
+    #[fail(display = "file expired: {}", key)]
+    Expired { key: String },
+    #[fail(display = "password required: {}", key)]
+    PasswordRequired { key: String },
+    #[fail(display = "invalid password: {}", key)]
+    InvalidPassword { key: String },
+    #[fail(display = "too many attempts: {}, retry in {}s", key, seconds)]
+    Throttled { key: String, seconds: u64 },
+    #[fail(display = "download limit reached: {}", key)]
+    Exhausted { key: String },
+    #[fail(display = "invalid download limit: {}", string)]
+    InvalidMaxDownloads { string: String },
+    #[fail(display = "no thumbnail: {}", key)]
+    NoThumbnail { key: String },
 }
 
-// {{{ Upload
+/// What the uploader told us about a file, besides its data.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadInfo {
+    pub name: String,
+    /// One of the configured expiry codes.
+    pub meta: String,
+    pub lock: Option<FileLock>,
+    pub max_downloads: Option<i32>,
+}
 
-#[post("/", data = "<data>", format = "multipart/form-data")]
-pub fn upload_http(
-    u: User,
-    data: FileUpload,
-    c: db::Connection,
-) -> Res<status::Created<JsonValue>> {
-    use crate::util::date::UtcDateTime;
-    use std::fs;
+/// Read the optional download limit of an upload, `burn` being the
+/// shortcut for a single download.
+pub fn max_downloads(max: Option<&str>, burn: Option<&str>) -> Res<Option<i32>> {
+    if let Some(burn) = burn {
+        match burn.trim() {
+            "1" | "true" | "on" | "yes" => return Ok(Some(1)),
+            "" | "0" | "false" | "off" | "no" => {}
+            _ => {
+                return Err(FileError::InvalidMaxDownloads {
+                    string: burn.into(),
+                }
+                .into())
+            }
+        }
+    }
+    match max.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(max) => match max.parse::<i32>() {
+            Ok(max) if max > 0 => Ok(Some(max)),
+            _ => Err(FileError::InvalidMaxDownloads { string: max.into() }.into()),
+        },
+    }
+}
 
-    assert!(Path::new(FLINK_DIR).is_dir());
-    assert!(Path::new(FLINK_DIR).join(STORE_DIR).is_dir());
-    assert!(Path::new(FLINK_DIR).join(SPOOL_DIR).is_dir());
+/// Failed share link passwords, per file and client.
+pub struct UnlockThrottle(pub Throttle);
 
-    let key = random_ascii(FILE_KEY_LEN);
+// {{{ Upload
 
-    if !user_store(u.id).is_dir() {
-        if let Err(e) = fs::create_dir(user_store(u.id)) {
-            println!("[file] failed to create user store directory");
-            println!("[file] {:?}", e);
+pub fn create_user_spool(user_id: i32) -> Res<()> {
+    if !user_spool(user_id).is_dir() {
+        if let Err(e) = std::fs::create_dir(user_spool(user_id)) {
+            error!("failed to create user spool directory: {}", e);
             return Err(e.into());
         }
     }
-    if !user_spool(u.id).is_dir() {
-        if let Err(e) = fs::create_dir(user_spool(u.id)) {
-            println!("[file] failed to create user spool directory");
-            println!("[file] {:?}", e);
-            return Err(e.into());
+    Ok(())
+}
+
+/// Move a finished upload from the spool into storage and record it.
+pub fn store_upload(
+    user_id: i32,
+    info: &UploadInfo,
+    spooled: &Path,
+    bytes: u64,
+    storage: &dyn Storage,
+    c: &db::Connection,
+) -> Res<File> {
+    use crate::util::date::UtcDateTime;
+
+    let config = config::get();
+    let key = random_ascii(config.key_length);
+
+    if let Err(e) = Quota::check_user(user_id, bytes as i64, c) {
+        if let Err(e) = std::fs::remove_file(spooled) {
+            warn!("failed to remove rejected upload: {:?}", e);
         }
+        return Err(e);
     }
 
-    let duration = match data.meta.as_str() {
-        "d" => chrono::Duration::days(1),
-        "w" => chrono::Duration::weeks(1),
-        "m" => chrono::Duration::weeks(4),
-        "q" => chrono::Duration::weeks(4 * 3),
-        "y" => chrono::Duration::weeks(4 * 12),
-        s => return Err(FileError::InvalidDuration { string: s.into() }.into()),
+    let duration = match config.expiry(&info.meta) {
+        Some(duration) => duration,
+        None => {
+            return Err(FileError::InvalidDuration {
+                string: info.meta.clone(),
+            }
+            .into())
+        }
     };
 
-    if let Err(e) = fs::rename(data.file, user_store_file(u.id, &key)) {
-        println!("[file] failed to move file from download location");
-        println!("[file] {:?}", e);
-        return Err(e.into());
+    let content_type = mime::sniff(spooled);
+    if let Err(e) = storage.put(user_id, &key, spooled) {
+        error!("failed to store file from spool location");
+        return Err(e);
     }
 
     let now = Utc::now();
     let end = now + duration;
+    let (password_phc, password_hash, password_salt) = FileLock::columns(info.lock.as_ref());
 
-    let file = File::create(
+    /* Checked again with the insert, other uploads may have finished since. */
+    let created = File::create_within_quota(
         &FileInsert {
-            user_id: u.id,
-            val: data.name.clone(),
-            key: key,
+            user_id: user_id,
+            val: filename::sanitize(&info.name),
+            key: key.clone(),
             upload_date: UtcDateTime(now).into(),
             delete_date: UtcDateTime(end).into(),
             downloads: 0,
-            bytes: data.size as i64,
+            bytes: bytes as i64,
+            password_hash: password_hash,
+            password_salt: password_salt,
+            max_downloads: info.max_downloads,
+            password_phc: password_phc,
+            content_type: content_type.map(String::from),
         },
-        &c,
-    )?;
+        c,
+    );
+    let file = match created {
+        Ok(file) => file,
+        Err(e) => {
+            if let Err(e) = storage.delete(user_id, &key) {
+                warn!("failed to remove rejected upload: {:?}", e);
+            }
+            return Err(e);
+        }
+    };
+    metrics::get().upload(bytes);
+    Ok(file)
+}
+
+#[post("/", data = "<data>", format = "multipart/form-data")]
+pub fn upload_http(
+    u: User,
+    data: FileUpload,
+    base: BaseUrl,
+    storage: State<SharedStorage>,
+    thumbnailer: State<Thumbnailer>,
+    c: db::Connection,
+) -> Res<status::Created<JsonValue>> {
+    create_user_spool(u.id)?;
 
-    Ok(status::Created(
-        file.url(),
-        Some(JsonValue(serde_json::to_value(&file)?)),
-    ))
+    let info = UploadInfo {
+        name: data.name.clone(),
+        meta: data.meta.clone(),
+        lock: data
+            .password
+            .as_ref()
+            .map(|password| FileLock::new(password.expose())),
+        max_downloads: max_downloads(
+            data.max_downloads.as_ref().map(String::as_str),
+            data.burn.as_ref().map(String::as_str),
+        )?,
+    };
+    let file = store_upload(u.id, &info, &data.file, data.size as u64, &**storage, &c)?;
+    thumbnailer.queue(&file);
+
+    let mut body = serde_json::to_value(&file)?;
+    body["url"] = file.url(&base).into();
+    body["download_url"] = file.download_url(&base).into();
+    body["view_url"] = file.view_url(&base).into();
+    body["protected"] = file.is_locked().into();
+    Ok(status::Created(file.url(&base), Some(JsonValue(body))))
 }
 
 #[post("/", data = "<_data>", format = "multipart/form-data", rank = 3)]
@@ -122,29 +235,324 @@ pub fn upload_forbidden(_data: FileUpload) -> Status {
 // }}}
 // {{{ Lookup
 
-#[get("/<key>")]
-pub fn lookup(key: String, c: db::Connection) -> Res<FileDownload> {
+fn available(key: &str, c: &db::Connection) -> Res<File> {
+    use crate::util::date::UtcDateTime;
+
+    let file = File::by_key(key, c)?;
+    if file.is_expired(UtcDateTime(Utc::now()).into()) {
+        info!("refusing expired file: {}", key);
+        return Err(FileError::Expired { key: key.into() }.into());
+    }
+    if file.is_exhausted() {
+        info!("refusing exhausted file: {}", key);
+        return Err(FileError::Exhausted { key: key.into() }.into());
+    }
+    Ok(file)
+}
+
+/// Check the password of a protected file, counting failures per client.
+/// An outdated hash is replaced while the password is at hand.
+fn unlock(
+    file: &mut File,
+    password: &str,
+    ip: &ClientIp,
+    throttle: &Throttle,
+    c: &db::Connection,
+) -> Res<bool> {
+    let lock = match file.lock() {
+        Some(lock) => lock,
+        None => return Ok(true),
+    };
+    let id = format!("{}@{}", file.key, ip);
+    if let Some(wait) = throttle.blocked(&id) {
+        warn!("throttled unlock of {} from {}", file.key, ip);
+        return Err(FileError::Throttled {
+            key: file.key.clone(),
+            seconds: wait.as_secs(),
+        }
+        .into());
+    }
+    if lock.is_valid(password) {
+        throttle.reset(&id);
+        if lock.needs_rehash() {
+            file.set_lock(Some(FileLock::new(password)));
+            file.update(c)?;
+        }
+        Ok(true)
+    } else {
+        warn!("wrong password for {} from {}", file.key, ip);
+        throttle.fail(&id);
+        Ok(false)
+    }
+}
+
+fn download(
+    file: File,
+    range: &RangeHeaders,
+    storage: &dyn Storage,
+    c: &db::Connection,
+) -> Res<FileDownload> {
+    let key = file.key.clone();
     c.transaction::<_, E, _>(|| {
-        let mut file = File::by_key(&key, &c)?;
-        match NamedFile::open(user_store_file(file.user_id, &file.key)) {
-            Ok(named_file) => {
-                file.downloads += 1;
-                file.update(&c)?;
-                Ok(FileDownload(named_file, file.val))
+        /* Limited files are only handed out whole, otherwise ranges that
+         * skip the first byte could fetch them without being counted. */
+        let ranges = match file.max_downloads {
+            Some(_) => Ranges::Full,
+            None => Ranges::from_headers(
+                &range,
+                file.bytes as u64,
+                &FileDownload::etag(&file),
+                &FileDownload::last_modified(&file),
+            ),
+        };
+        /* `file` may be stale by now, the claim decides. */
+        let counted = FileDownload::is_download(&ranges, range, file.bytes as u64);
+        if counted && !File::claim_download(file.id, c)? {
+            return Err(FileError::Exhausted { key: key.clone() }.into());
+        }
+        match FileDownload::open(&file, &ranges, storage) {
+            Ok(mut download) => {
+                download.body = metrics::get().count_download(download.body);
+                if counted {
+                    metrics::get().download();
+                    if File::by_id(file.id, c)?.is_exhausted() {
+                        burn(&file, storage, c)?;
+                    }
+                }
+                Ok(download)
             }
             Err(error) => {
-                println!("[file] failed to open named file: {}", key);
-                Err(error.into())
+                error!("failed to open stored file: {}", key);
+                Err(error)
             }
         }
     })
 }
 
+/// Remove a file that reached its download limit. The download already
+/// holds an open stream, so it can finish; if the blob can't be removed
+/// the record stays behind, refusing further downloads, for the reaper.
+fn burn(file: &File, storage: &dyn Storage, c: &db::Connection) -> Res<()> {
+    info!("{} reached its download limit", file);
+    match storage.delete(file.user_id, &file.key) {
+        Ok(_) => {
+            File::delete(file.id, c)?;
+            thumbnail::remove(&file.key);
+        }
+        Err(error) => error!("failed to burn {}: {:?}", file, error),
+    }
+    Ok(())
+}
+
+#[get("/<key>")]
+pub fn lookup(
+    key: String,
+    password: SharePassword,
+    ip: ClientIp,
+    throttle: State<UnlockThrottle>,
+    range: RangeHeaders,
+    storage: State<SharedStorage>,
+    c: db::Connection,
+) -> Res<Lookup> {
+    let mut file = available(&key, &c)?;
+    if file.is_locked() {
+        match password.0 {
+            Some(ref password) => {
+                if !unlock(&mut file, password.expose(), &ip, &throttle.0, &c)? {
+                    return Err(FileError::InvalidPassword { key: key }.into());
+                }
+            }
+            None => {
+                return Ok(Lookup::Locked(UnlockForm {
+                    key: file.key,
+                    failed: false,
+                    view: false,
+                }))
+            }
+        }
+    }
+    Ok(Lookup::Download(download(file, &range, &**storage, &c)?))
+}
+
+#[derive(FromForm)]
+pub struct Unlock {
+    pub password: Secret,
+}
+
+#[post(
+    "/<key>",
+    data = "<data>",
+    format = "application/x-www-form-urlencoded"
+)]
+pub fn lookup_unlock(
+    key: String,
+    data: Form<Unlock>,
+    ip: ClientIp,
+    throttle: State<UnlockThrottle>,
+    range: RangeHeaders,
+    storage: State<SharedStorage>,
+    c: db::Connection,
+) -> Res<Lookup> {
+    let mut file = available(&key, &c)?;
+    if !unlock(&mut file, data.password.expose(), &ip, &throttle.0, &c)? {
+        return Ok(Lookup::Locked(UnlockForm {
+            key: file.key,
+            failed: true,
+            view: false,
+        }));
+    }
+    Ok(Lookup::Download(download(file, &range, &**storage, &c)?))
+}
+
+/// Like `lookup`, but images, PDFs, audio, video and text open in the
+/// browser instead of being saved.
+#[get("/<key>/view")]
+pub fn view(
+    key: String,
+    password: SharePassword,
+    ip: ClientIp,
+    throttle: State<UnlockThrottle>,
+    range: RangeHeaders,
+    storage: State<SharedStorage>,
+    c: db::Connection,
+) -> Res<Lookup> {
+    Ok(lookup(key, password, ip, throttle, range, storage, c)?.inline())
+}
+
+#[post(
+    "/<key>/view",
+    data = "<data>",
+    format = "application/x-www-form-urlencoded"
+)]
+pub fn view_unlock(
+    key: String,
+    data: Form<Unlock>,
+    ip: ClientIp,
+    throttle: State<UnlockThrottle>,
+    range: RangeHeaders,
+    storage: State<SharedStorage>,
+    c: db::Connection,
+) -> Res<Lookup> {
+    Ok(lookup_unlock(key, data, ip, throttle, range, storage, c)?.inline())
+}
+
+#[head("/<key>")]
+pub fn lookup_head(
+    key: String,
+    password: SharePassword,
+    ip: ClientIp,
+    throttle: State<UnlockThrottle>,
+    storage: State<SharedStorage>,
+    c: db::Connection,
+) -> Res<FileDownload> {
+    let mut file = available(&key, &c)?;
+    if file.is_locked() {
+        require_password(&mut file, &password, &ip, &throttle.0, &c)?;
+    }
+    match FileDownload::head(&file, &**storage) {
+        Ok(head) => Ok(head),
+        Err(error) => {
+            error!("failed to stat stored file: {}", key);
+            Err(error)
+        }
+    }
+}
+
+/// Scripts send the password of a protected file in a header.
+fn require_password(
+    file: &mut File,
+    password: &SharePassword,
+    ip: &ClientIp,
+    throttle: &Throttle,
+    c: &db::Connection,
+) -> Res<()> {
+    match password.0 {
+        Some(ref password) => match unlock(file, password.expose(), ip, throttle, c)? {
+            true => Ok(()),
+            false => Err(FileError::InvalidPassword {
+                key: file.key.clone(),
+            }
+            .into()),
+        },
+        None => Err(FileError::PasswordRequired {
+            key: file.key.clone(),
+        }
+        .into()),
+    }
+}
+
+/// The thumbnail of an image, for its owner and whoever may download it.
+/// Files with a download limit only show theirs to the owner.
+#[get("/<key>/thumb")]
+pub fn thumb(
+    key: String,
+    u: Option<User>,
+    password: SharePassword,
+    ip: ClientIp,
+    throttle: State<UnlockThrottle>,
+    c: db::Connection,
+) -> Res<NamedFile> {
+    let mut file = available(&key, &c)?;
+    let owner = u.map_or(false, |u| u.id == file.user_id);
+    /* A preview would show the content without using up a download. */
+    if file.max_downloads.is_some() && !owner {
+        return Err(FileError::NoThumbnail { key: key }.into());
+    }
+    if file.is_locked() && !owner {
+        require_password(&mut file, &password, &ip, &throttle.0, &c)?;
+    }
+    match NamedFile::open(thumbnail::path(&file.key)) {
+        Ok(thumb) => Ok(thumb),
+        Err(_) => Err(FileError::NoThumbnail { key: key }.into()),
+    }
+}
+
+// }}}
+// {{{ Password
+
+#[derive(Deserialize, Debug)]
+pub struct SetPassword {
+    /// The new password, `null` or empty to remove it.
+    pub password: Option<Secret>,
+}
+
+#[put("/<key>/password", data = "<data>", format = "application/json")]
+pub fn password(
+    u: User,
+    key: String,
+    data: Json<SetPassword>,
+    c: db::Connection,
+) -> Res<JsonValue> {
+    let mut file = File::by_key(&key, &c)?;
+
+    if file.user_id != u.id {
+        return Err(FileError::PermissionDenied {
+            file: file,
+            user: u,
+        }
+        .into());
+    }
+
+    let lock = data
+        .into_inner()
+        .password
+        .filter(|password| !password.is_empty())
+        .map(|password| FileLock::new(password.expose()));
+    file.set_lock(lock);
+    file.update(&c)?;
+    Ok(json!({"key": file.key, "protected": file.is_locked()}))
+}
+
 // }}}
 // {{{ Delete
 
 #[delete("/<key>")]
-pub fn delete(u: User, key: String, c: db::Connection) -> Res<JsonValue> {
+pub fn delete(
+    u: User,
+    key: String,
+    storage: State<SharedStorage>,
+    c: db::Connection,
+) -> Res<JsonValue> {
     let file = File::by_key(&key, &c)?;
 
     if file.user_id != u.id {
@@ -155,12 +563,19 @@ pub fn delete(u: User, key: String, c: db::Connection) -> Res<JsonValue> {
         .into());
     }
 
-    if let Err(error) = std::fs::remove_file(user_store_file(file.user_id, &file.key)) {
-        File::delete(file.id, &c)?;
-        Err(error.into())
+    remove(&file, &**storage, &c)?;
+    Ok(json!({"key": file.key}))
+}
+
+/// Remove a file's blob and record.
+pub fn remove(file: &File, storage: &dyn Storage, c: &db::Connection) -> Res<()> {
+    thumbnail::remove(&file.key);
+    if let Err(error) = storage.delete(file.user_id, &file.key) {
+        File::delete(file.id, c)?;
+        Err(error)
     } else {
-        File::delete(file.id, &c)?;
-        Ok(json!({"key": file.key}))
+        File::delete(file.id, c)?;
+        Ok(())
     }
 }
 
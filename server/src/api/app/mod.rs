@@ -1,2 +1,4 @@
 pub mod files;
 pub use files::*;
+
+pub mod tus;
@@ -0,0 +1,166 @@
+use crate::api::app::files::{
+    create_user_spool, max_downloads, store_upload, FileError, UploadInfo,
+};
+use crate::api::BaseUrl;
+use crate::config;
+use crate::db;
+use crate::model::{FileLock, Quota, User};
+use crate::storage::SharedStorage;
+use crate::task::Thumbnailer;
+use crate::util::{
+    error::Res,
+    random::random_ascii,
+    tus::{TusError, TusHeaders, TusResponse, TusUpload, TUS_EXTENSIONS, TUS_VERSION},
+};
+
+use rocket::{http::Status, Data, State};
+use std::io::{self, Read};
+
+pub const RES_TUS: &'static str = "file/tus";
+
+// {{{ Discovery
+
+#[options("/")]
+pub fn options() -> TusResponse {
+    TusResponse::new(Status::NoContent)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", config::get().size_limit)
+}
+
+// }}}
+// {{{ Creation
+
+#[post("/")]
+pub fn create(u: User, tus: TusHeaders, base: BaseUrl, c: db::Connection) -> Res<TusResponse> {
+    tus.check_version()?;
+
+    let config = config::get();
+    let length = tus.length()?;
+    if length > config.size_limit {
+        return Err(TusError::TooLarge {
+            length: length,
+            limit: config.size_limit,
+        }
+        .into());
+    }
+
+    /* Reject bad expiry codes and quotas now rather than after the last byte. */
+    Quota::check_user(u.id, length as i64, &c)?;
+    let meta = tus.metadata("meta")?;
+    if config.expiry(&meta).is_none() {
+        return Err(FileError::InvalidDuration { string: meta }.into());
+    }
+
+    create_user_spool(u.id)?;
+    let upload = TusUpload {
+        id: random_ascii(config.key_length),
+        user_id: u.id,
+        length: length,
+        info: UploadInfo {
+            name: tus.metadata("name")?,
+            meta: meta,
+            lock: tus
+                .metadata_opt("password")?
+                .filter(|password| !password.is_empty())
+                .map(|password| FileLock::new(&password)),
+            max_downloads: max_downloads(
+                tus.metadata_opt("max_downloads")?
+                    .as_ref()
+                    .map(String::as_str),
+                tus.metadata_opt("burn")?.as_ref().map(String::as_str),
+            )?,
+        },
+    };
+    upload.create()?;
+    info!("user[{}] created upload {}", u.id, upload.id);
+
+    Ok(TusResponse::new(Status::Created)
+        .header(
+            "Location",
+            format!("{}/{}", base.collection_url(RES_TUS), upload.id),
+        )
+        .header("Upload-Offset", 0))
+}
+
+// }}}
+// {{{ Offset
+
+#[head("/<id>")]
+pub fn offset(u: User, id: String, tus: TusHeaders) -> Res<TusResponse> {
+    tus.check_version()?;
+    let upload = TusUpload::load(u.id, &id)?;
+    Ok(TusResponse::new(Status::Ok)
+        .header("Upload-Offset", upload.offset()?)
+        .header("Upload-Length", upload.length)
+        .header("Cache-Control", "no-store"))
+}
+
+// }}}
+// {{{ Append
+
+#[patch("/<id>", data = "<data>")]
+pub fn append(
+    u: User,
+    id: String,
+    tus: TusHeaders,
+    data: Data,
+    storage: State<SharedStorage>,
+    thumbnailer: State<Thumbnailer>,
+    c: db::Connection,
+) -> Res<TusResponse> {
+    tus.check_version()?;
+    tus.check_content_type()?;
+
+    let upload = TusUpload::load(u.id, &id)?;
+    /* Held from the offset check until the upload is stored or abandoned. */
+    let mut part = upload.lock()?;
+    let expected = part.metadata()?.len();
+    let found = tus.offset()?;
+    if found != expected {
+        return Err(TusError::OffsetMismatch { expected, found }.into());
+    }
+
+    /* Whatever arrives before a dropped connection stays in the part file. */
+    let written = io::copy(&mut data.open().take(upload.length - expected), &mut part)?;
+    let offset = expected + written;
+
+    if offset == upload.length {
+        let stored = store_upload(
+            u.id,
+            &upload.info,
+            &upload.part(),
+            upload.length,
+            &**storage,
+            &c,
+        );
+        /* A rejected upload, e.g. over quota, can't be resumed. */
+        let file = match stored {
+            Ok(file) => file,
+            Err(error) => {
+                upload.remove()?;
+                warn!("user[{}] upload {} not stored: {}", u.id, id, error);
+                return Err(error);
+            }
+        };
+        upload.finish()?;
+        thumbnailer.queue(&file);
+        info!("user[{}] finished upload {} -> {}", u.id, id, file);
+    }
+
+    Ok(TusResponse::new(Status::NoContent).header("Upload-Offset", offset))
+}
+
+// }}}
+// {{{ Termination
+
+#[delete("/<id>")]
+pub fn terminate(u: User, id: String, tus: TusHeaders) -> Res<TusResponse> {
+    tus.check_version()?;
+    let upload = TusUpload::load(u.id, &id)?;
+    upload.remove()?;
+    info!("user[{}] terminated upload {}", u.id, id);
+    Ok(TusResponse::new(Status::NoContent))
+}
+
+// }}}
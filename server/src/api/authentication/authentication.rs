@@ -1,35 +1,118 @@
+use crate::api::{BaseUrl, ClientIp, Device};
 use crate::db;
-use crate::model::{Session, User};
-use crate::util::error::Res;
+use crate::model::{AuthenticationError, Lockout, Session, User, LOCKOUT_ACCOUNT, LOCKOUT_IP};
+use crate::util::{
+    error::{ClientError, Error as E, Res},
+    secret::Secret,
+    throttle::Backoff,
+};
 
 use rocket::{
     http::{Cookies, Status},
     request::Form,
     response::status,
+    State,
 };
 use rocket_contrib::json::{Json, JsonValue};
 
 // {{{ Login
 
-#[derive(FromForm, Serialize, Deserialize, Debug)]
+#[derive(FromForm, Deserialize, Debug)]
 pub struct Login {
     pub username: String,
-    pub password: String,
+    pub password: Secret,
 }
 
-pub fn login(login: Login, c: db::Connection, mut cookies: Cookies) -> Res<JsonValue> {
-    let session = User::login(&login.username, &login.password, &c, &mut cookies)?;
-    Ok(json!({ "token": session.token }))
+/// Failed logins per client address and per username, whether it exists or not.
+pub struct LoginThrottle {
+    pub ip: Backoff,
+    pub account: Backoff,
+    pub lockout: std::time::Duration,
+}
+
+impl LoginThrottle {
+    fn check(&self, ip: &str, account: &str) -> Res<()> {
+        let wait = match (self.ip.blocked(ip), self.account.blocked(account)) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        match wait {
+            Some(wait) => Err(AuthenticationError::Throttled {
+                seconds: wait.as_secs() + 1,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    fn fail(&self, ip: &str, account: &str, c: &db::Connection) -> Res<()> {
+        if let Some(failures) = self.ip.fail(ip) {
+            Lockout::record(LOCKOUT_IP, ip, failures, self.lockout, c)?;
+        }
+        if let Some(failures) = self.account.fail(account) {
+            Lockout::record(LOCKOUT_ACCOUNT, account, failures, self.lockout, c)?;
+        }
+        Ok(())
+    }
+
+    /// A successful login clears the account only. The address keeps its
+    /// failures, or one valid account would let it guess all the others.
+    fn reset(&self, account: &str) {
+        self.account.reset(account);
+    }
+}
+
+pub fn login(
+    login: Login,
+    device: Device,
+    ip: ClientIp,
+    throttle: State<LoginThrottle>,
+    c: db::Connection,
+    mut cookies: Cookies,
+) -> Res<JsonValue> {
+    let ip = ip.to_string();
+    throttle.check(&ip, &login.username)?;
+    match User::login(
+        &login.username,
+        login.password.expose(),
+        &device,
+        &c,
+        &mut cookies,
+    ) {
+        Ok(session) => {
+            throttle.reset(&login.username);
+            Ok(json!({ "token": session.token.expose() }))
+        }
+        Err(E::ClientError(ClientError::AuthenticationError { error })) => {
+            throttle.fail(&ip, &login.username, &c)?;
+            Err(error.into())
+        }
+        Err(error) => Err(error),
+    }
 }
 
 #[post("/", data = "<data>", format = "application/json")]
-pub fn login_json(data: Json<Login>, c: db::Connection, cookies: Cookies) -> Res<JsonValue> {
-    login(data.into_inner(), c, cookies)
+pub fn login_json(
+    data: Json<Login>,
+    device: Device,
+    ip: ClientIp,
+    throttle: State<LoginThrottle>,
+    c: db::Connection,
+    cookies: Cookies,
+) -> Res<JsonValue> {
+    login(data.into_inner(), device, ip, throttle, c, cookies)
 }
 
 #[post("/", data = "<data>", format = "application/x-www-form-urlencoded")]
-pub fn login_http(data: Form<Login>, c: db::Connection, cookies: Cookies) -> Res<JsonValue> {
-    login(data.into_inner(), c, cookies)
+pub fn login_http(
+    data: Form<Login>,
+    device: Device,
+    ip: ClientIp,
+    throttle: State<LoginThrottle>,
+    c: db::Connection,
+    cookies: Cookies,
+) -> Res<JsonValue> {
+    login(data.into_inner(), device, ip, throttle, c, cookies)
 }
 
 // }}}
@@ -46,28 +129,31 @@ pub fn logout(c: db::Connection, mut cookies: Cookies) -> Res<JsonValue> {
 // }}}
 // {{{ Register
 
-#[derive(FromForm, Serialize, Deserialize, Debug)]
+#[derive(FromForm, Deserialize, Debug)]
 pub struct Register {
     pub username: String,
-    pub password_one: String,
-    pub password_two: String,
+    pub password_one: Secret,
+    pub password_two: Secret,
 }
 
 pub fn register(
     register: Register,
+    base: BaseUrl,
+    device: Device,
     c: db::Connection,
     mut cookies: Cookies,
 ) -> Res<status::Created<JsonValue>> {
     match User::register(
         &register.username,
-        &register.password_one,
-        &register.password_two,
+        register.password_one.expose(),
+        register.password_two.expose(),
+        &device,
         &c,
         &mut cookies,
     ) {
         Ok((user, _, session)) => Ok(status::Created(
-            user.url(),
-            Some(json!({ "token": session.token })),
+            user.url(&base),
+            Some(json!({ "token": session.token.expose() })),
         )),
         Err(error) => Err(error),
     }
@@ -76,19 +162,23 @@ pub fn register(
 #[post("/", data = "<data>", format = "application/json")]
 pub fn register_json(
     data: Json<Register>,
+    base: BaseUrl,
+    device: Device,
     c: db::Connection,
     cookies: Cookies,
 ) -> Res<status::Created<JsonValue>> {
-    register(data.into_inner(), c, cookies)
+    register(data.into_inner(), base, device, c, cookies)
 }
 
 #[post("/", data = "<data>", format = "application/x-www-form-urlencoded")]
 pub fn register_http(
     data: Form<Register>,
+    base: BaseUrl,
+    device: Device,
     c: db::Connection,
     cookies: Cookies,
 ) -> Res<status::Created<JsonValue>> {
-    register(data.into_inner(), c, cookies)
+    register(data.into_inner(), base, device, c, cookies)
 }
 
 // }}}
@@ -1,2 +1,5 @@
 pub mod authentication;
 pub use authentication::*;
+
+pub mod sessions;
+pub mod tokens;
@@ -0,0 +1,47 @@
+use crate::db;
+use crate::model::{Session, SessionError};
+use crate::util::error::Res;
+
+use rocket::http::Status;
+use rocket_contrib::json::JsonValue;
+
+// {{{ Query
+
+#[get("/")]
+pub fn query(s: Session, c: db::Connection) -> Res<JsonValue> {
+    let sessions = Session::by_user(s.user_id, &c)?
+        .into_iter()
+        .map(|session| {
+            let current = session.id == s.id;
+            let mut value = serde_json::to_value(&session)?;
+            value["current"] = current.into();
+            Ok(value)
+        })
+        .collect::<Res<Vec<_>>>()?;
+    Ok(JsonValue(sessions.into()))
+}
+
+#[get("/", rank = 3)]
+pub fn query_forbidden() -> Status {
+    Status::Forbidden
+}
+
+// }}}
+// {{{ Revoke
+
+#[delete("/<id>")]
+pub fn revoke(s: Session, id: i32, c: db::Connection) -> Res<JsonValue> {
+    match Session::revoke(id, s.user_id, &c)? {
+        0 => Err(SessionError::NotFound { id }.into()),
+        _ => Ok(json!({ "revoked": 1 })),
+    }
+}
+
+/// Log out every other device, keeping the current session.
+#[delete("/")]
+pub fn revoke_others(s: Session, c: db::Connection) -> Res<JsonValue> {
+    let revoked = Session::revoke_others(s.user_id, s.id, &c)?;
+    Ok(json!({ "revoked": revoked }))
+}
+
+// }}}
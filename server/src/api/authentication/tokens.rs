@@ -0,0 +1,72 @@
+use crate::db;
+use crate::model::{Session, Token, TokenError, User};
+use crate::util::{date::UtcDateTime, error::Res};
+
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket_contrib::json::{Json, JsonValue};
+
+// {{{ Query
+
+#[get("/")]
+pub fn query(u: User, c: db::Connection) -> Res<JsonValue> {
+    Ok(JsonValue(serde_json::to_value(&Token::by_user(u.id, &c)?)?))
+}
+
+#[get("/", rank = 3)]
+pub fn query_forbidden() -> Status {
+    Status::Forbidden
+}
+
+// }}}
+// {{{ Create
+
+#[derive(Deserialize, Debug)]
+pub struct CreateToken {
+    pub name: String,
+    /// Days until the token stops working, it never expires if omitted.
+    pub expires_in_days: Option<i64>,
+}
+
+/// The secret is only ever part of this response. Tokens are managed with
+/// a login session only, so a leaked token can't mint or revoke others.
+#[post("/", data = "<data>", format = "application/json")]
+pub fn create(s: Session, data: Json<CreateToken>, c: db::Connection) -> Res<JsonValue> {
+    let expires_at = match data.expires_in_days {
+        Some(days) if days > 0 && days <= 36500 => {
+            Some(UtcDateTime(Utc::now() + Duration::days(days)).into())
+        }
+        Some(days) => {
+            return Err(TokenError::InvalidExpiry {
+                string: days.to_string(),
+            }
+            .into())
+        }
+        None => None,
+    };
+    let (token, secret) = Token::create(s.user_id, &data.name, expires_at, &c)?;
+    let mut body = serde_json::to_value(&token)?;
+    body["token"] = secret.into();
+    Ok(JsonValue(body))
+}
+
+#[post("/", rank = 3)]
+pub fn create_forbidden() -> Status {
+    Status::Forbidden
+}
+
+// }}}
+// {{{ Revoke
+
+#[delete("/<id>")]
+pub fn revoke(s: Session, id: i32, c: db::Connection) -> Res<JsonValue> {
+    Token::revoke(id, s.user_id, &c)?;
+    Ok(json!({ "id": id }))
+}
+
+#[delete("/<_id>", rank = 3)]
+pub fn revoke_forbidden(_id: i32) -> Status {
+    Status::Forbidden
+}
+
+// }}}
@@ -0,0 +1,38 @@
+use crate::db::connection::SqlitePool;
+use crate::task::Health;
+
+use rocket::{http::Status, response::status, State};
+use rocket_contrib::json::JsonValue;
+
+/// Names and outcomes only; what exactly failed goes to the log.
+fn report(health: &Health) -> JsonValue {
+    for check in health.failed() {
+        warn!("{} check failed: {}", check.name, check.message);
+    }
+    let checks: Vec<JsonValue> = health
+        .checks
+        .iter()
+        .map(|check| json!({"name": check.name, "ok": check.ok}))
+        .collect();
+    json!({
+        "status": if health.ok() { "ok" } else { "failing" },
+        "checks": checks,
+    })
+}
+
+/// Liveness: answers as long as the service does, with the checks attached.
+#[get("/healthz")]
+pub fn healthz(pool: State<SqlitePool>) -> JsonValue {
+    report(&Health::check(&pool))
+}
+
+/// Readiness: `503` while any check fails, so no traffic is sent here.
+#[get("/readyz")]
+pub fn readyz(pool: State<SqlitePool>) -> status::Custom<JsonValue> {
+    let health = Health::check(&pool);
+    let status = match health.ok() {
+        true => Status::Ok,
+        false => Status::ServiceUnavailable,
+    };
+    status::Custom(status, report(&health))
+}
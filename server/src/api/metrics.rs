@@ -0,0 +1,24 @@
+use crate::api::ClientIp;
+use crate::config;
+use crate::db::connection::SqlitePool;
+use crate::util::metrics;
+
+use rocket::{
+    http::{ContentType, Status},
+    response::content::Content,
+    State,
+};
+
+/// Prometheus metrics, for the addresses in `metrics.allow` only.
+#[get("/metrics")]
+pub fn metrics(ip: ClientIp, pool: State<SqlitePool>) -> Result<Content<String>, Status> {
+    let config = config::get();
+    let allowed = match ip.0 {
+        Some(ip) => config.metrics.enabled && config.metrics.allow.contains(&ip),
+        None => false,
+    };
+    if !allowed {
+        return Err(Status::NotFound);
+    }
+    Ok(Content(ContentType::Plain, metrics::get().render(&pool)))
+}
@@ -1,17 +1,291 @@
 pub mod app;
 pub mod authentication;
+pub mod health;
+pub mod metrics;
+pub mod user;
 pub use app::*;
 
-pub const API_PROT: &'static str = "https";
-pub const API_HOST: &'static str = "flink.com";
+use crate::config::{self, ClientIpHeader};
+use rocket::{
+    request::{self, FromRequest},
+    Outcome, Request,
+};
+use std::net::IpAddr;
+
 pub const API_BASE: &'static str = "api";
 pub const RES_USER: &'static str = "user";
 pub const RES_FILE: &'static str = "file";
+pub const RES_DOWNLOAD: &'static str = "f";
+
+/// The public origin links are generated against, e.g. `https://flink.com`.
+#[derive(Debug, Clone)]
+pub struct BaseUrl(pub String);
+
+impl BaseUrl {
+    /// The configured `public_url`, if any.
+    pub fn configured() -> Option<Self> {
+        config::get()
+            .public_url
+            .as_ref()
+            .map(|url| BaseUrl(url.trim_end_matches('/').into()))
+    }
+
+    /// The configured `public_url`, then the forwarded origin if the peer is
+    /// a trusted proxy, and finally the request's `Host` header.
+    pub fn from_request_headers(request: &Request) -> Self {
+        if let Some(base) = Self::configured() {
+            return base;
+        }
+        let config = config::get();
+
+        let headers = request.headers();
+        let trusted = match request.remote() {
+            Some(remote) => config.trusted_proxies.contains(&remote.ip()),
+            None => false,
+        };
+        if trusted {
+            if let Some(host) = Self::last(headers.get_one("X-Forwarded-Host")) {
+                let prot = match Self::last(headers.get_one("X-Forwarded-Proto")) {
+                    Some("https") => "https",
+                    _ => "http",
+                };
+                return BaseUrl(format!("{}://{}", prot, host));
+            }
+        }
+
+        match Self::last(headers.get_one("Host")) {
+            Some(host) => BaseUrl(format!("http://{}", host)),
+            None => BaseUrl(String::from("http://localhost")),
+        }
+    }
+
+    /// Proxies append to these headers, so only the last value was set by
+    /// our proxy; anything before it came from the client.
+    fn last(value: Option<&str>) -> Option<&str> {
+        value
+            .and_then(|value| value.rsplit(',').next())
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    pub fn collection_url(&self, res: &str) -> String {
+        format!("{}/{}/{}", self.0, API_BASE, res)
+    }
+
+    pub fn resource_url(&self, res: &str, id: i32) -> String {
+        format!("{}/{}", self.collection_url(res), id)
+    }
+
+    pub fn download_url(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.0, RES_DOWNLOAD, key)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for BaseUrl {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(BaseUrl::from_request_headers(request))
+    }
+}
+
+/// The address of the client, as reported by a trusted proxy if there is one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    /// A trusted proxy sets `X-Real-IP` to its peer, or appends that to
+    /// `X-Forwarded-For`, whichever `client_ip_header` says. Headers of
+    /// anyone else are ignored.
+    pub fn from_request_headers(request: &Request) -> Self {
+        let config = config::get();
+        let headers = request.headers();
+        Self::resolve(
+            request.remote().map(|remote| remote.ip()),
+            headers.get_one("X-Real-IP"),
+            headers.get_one("X-Forwarded-For"),
+            &config.trusted_proxies,
+            config.client_ip_header,
+        )
+    }
+
+    fn resolve(
+        remote: Option<IpAddr>,
+        real_ip: Option<&str>,
+        forwarded_for: Option<&str>,
+        trusted: &[IpAddr],
+        header: ClientIpHeader,
+    ) -> Self {
+        let proxied = remote.map_or(false, |ip| trusted.contains(&ip));
+        if proxied {
+            let forwarded = match header {
+                ClientIpHeader::XRealIp => real_ip.and_then(|ip| ip.trim().parse().ok()),
+                ClientIpHeader::XForwardedFor => {
+                    forwarded_for.and_then(|chain| Self::forwarded(chain, trusted))
+                }
+            };
+            if forwarded.is_some() {
+                return ClientIp(forwarded);
+            }
+        }
+        ClientIp(remote)
+    }
 
-pub fn collection_url(res: &str) -> String {
-    format!("{}://{}/{}/{}", API_PROT, API_HOST, API_BASE, res)
+    /// The client in an `X-Forwarded-For` chain. Each proxy appends its
+    /// peer, so walking from the right the first address that isn't one
+    /// of our proxies is the client; anything left of it came from the
+    /// client and can't be trusted.
+    fn forwarded(chain: &str, trusted: &[IpAddr]) -> Option<IpAddr> {
+        let mut client = None;
+        for entry in chain.rsplit(',') {
+            match entry.trim().parse() {
+                Ok(ip) => {
+                    client = Some(ip);
+                    if !trusted.contains(&ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        client
+    }
 }
 
-pub fn resource_url(res: &str, id: i32) -> String {
-    format!("{}/{}", collection_url(res), id)
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(ClientIp::from_request_headers(request))
+    }
+}
+
+/// What a session remembers about the client that logged in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+const USER_AGENT_LEN: usize = 256;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Device {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let user_agent = request
+            .headers()
+            .get_one("User-Agent")
+            .map(|agent| agent.chars().take(USER_AGENT_LEN).collect());
+        let ip = ClientIp::from_request_headers(request)
+            .0
+            .map(|ip| ip.to_string());
+        Outcome::Success(Device { user_agent, ip })
+    }
+}
+
+use core::fmt::{Display, Error as FmtError, Formatter};
+impl Display for ClientIp {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self.0 {
+            Some(ip) => write!(f, "{}", ip),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn takes_the_host_our_proxy_set() {
+        assert_eq!(BaseUrl::last(Some("flink.example")), Some("flink.example"));
+        assert_eq!(
+            BaseUrl::last(Some("evil.example, flink.example")),
+            Some("flink.example")
+        );
+        assert_eq!(BaseUrl::last(Some("flink.example, ")), None);
+        assert_eq!(BaseUrl::last(None), None);
+    }
+
+    #[test]
+    fn takes_the_address_our_proxy_saw() {
+        let trusted = vec![ip("127.0.0.1").unwrap()];
+        assert_eq!(ClientIp::forwarded("1.2.3.4", &trusted), ip("1.2.3.4"));
+        /* Whatever the client put in front is ignored. */
+        assert_eq!(
+            ClientIp::forwarded("127.0.0.1, 1.2.3.4", &trusted),
+            ip("1.2.3.4")
+        );
+        assert_eq!(
+            ClientIp::forwarded("garbage, 10.0.0.1,1.2.3.4", &trusted),
+            ip("1.2.3.4")
+        );
+    }
+
+    #[test]
+    fn skips_trusted_proxies() {
+        let trusted = vec![ip("127.0.0.1").unwrap(), ip("10.0.0.2").unwrap()];
+        assert_eq!(
+            ClientIp::forwarded("127.0.0.1, 1.2.3.4, 10.0.0.2", &trusted),
+            ip("1.2.3.4")
+        );
+        assert_eq!(
+            ClientIp::forwarded("::1, 2001:db8::1, 10.0.0.2", &trusted),
+            ip("2001:db8::1")
+        );
+        /* A chain of proxies only: the farthest one is the client. */
+        assert_eq!(
+            ClientIp::forwarded("10.0.0.2, 127.0.0.1", &trusted),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn ignores_a_forged_real_ip() {
+        let trusted = vec![ip("127.0.0.1").unwrap()];
+        /* The proxy appends to X-Forwarded-For and passes X-Real-IP on. */
+        let client = ClientIp::resolve(
+            ip("127.0.0.1"),
+            Some("127.0.0.1"),
+            Some("1.2.3.4"),
+            &trusted,
+            ClientIpHeader::XForwardedFor,
+        );
+        assert_eq!(client, ClientIp(ip("1.2.3.4")));
+        /* A proxy that overwrites X-Real-IP is believed when configured. */
+        let client = ClientIp::resolve(
+            ip("127.0.0.1"),
+            Some("5.6.7.8"),
+            Some("127.0.0.1, 5.6.7.8"),
+            &trusted,
+            ClientIpHeader::XRealIp,
+        );
+        assert_eq!(client, ClientIp(ip("5.6.7.8")));
+        /* Without a trusted proxy in between neither header counts. */
+        let client = ClientIp::resolve(
+            ip("9.9.9.9"),
+            Some("127.0.0.1"),
+            Some("127.0.0.1"),
+            &trusted,
+            ClientIpHeader::XRealIp,
+        );
+        assert_eq!(client, ClientIp(ip("9.9.9.9")));
+    }
+
+    #[test]
+    fn stops_at_garbage() {
+        let trusted = vec![ip("127.0.0.1").unwrap()];
+        assert_eq!(ClientIp::forwarded("", &trusted), None);
+        assert_eq!(ClientIp::forwarded("unknown", &trusted), None);
+        assert_eq!(
+            ClientIp::forwarded("1.2.3.4, nonsense, 127.0.0.1", &trusted),
+            ip("127.0.0.1")
+        );
+    }
 }
@@ -0,0 +1,2 @@
+pub mod quota;
+pub use quota::*;
@@ -0,0 +1,29 @@
+use crate::db;
+use crate::model::{Quota, Usage, User};
+use crate::util::error::Res;
+
+use rocket::http::Status;
+use rocket_contrib::json::JsonValue;
+
+// {{{ Query
+
+#[get("/")]
+pub fn query(u: User, c: db::Connection) -> Res<JsonValue> {
+    let quota = Quota::by_user(u.id, &c)?;
+    let usage = Usage::of(u.id, &c)?;
+    Ok(json!({
+        "usage": usage,
+        "quota": {
+            "max_bytes": quota.max_bytes,
+            "max_files": quota.max_files,
+            "max_file_bytes": quota.max_file_bytes,
+        }
+    }))
+}
+
+#[get("/", rank = 3)]
+pub fn query_forbidden() -> Status {
+    Status::Forbidden
+}
+
+// }}}
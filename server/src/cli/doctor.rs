@@ -0,0 +1,57 @@
+use crate::cli::{CliError, CliResult};
+use crate::db;
+use crate::task::Health;
+use crate::util::arg::Opt;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+#[derive(Debug)]
+pub struct DoctorCommand {
+    json: bool,
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("doctor")
+        .about("Run the startup checks without starting the service")
+        .arg(
+            Arg::with_name("JSON")
+                .long("json")
+                .help("Print the checks as JSON"),
+        )
+}
+
+pub fn parse(args: &ArgMatches) -> DoctorCommand {
+    DoctorCommand {
+        json: Opt::_flag(args, "JSON"),
+    }
+}
+
+pub fn run(command: DoctorCommand) -> CliResult {
+    let health = match db::Connection::try_pool() {
+        Ok(pool) => Health::check(&pool),
+        Err(error) => Health::unreachable(error.to_string()),
+    };
+
+    if command.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&health).map_err(crate::util::error::Error::from)?
+        );
+    } else {
+        print(&health);
+    }
+
+    match health.failed().count() {
+        0 => Ok(()),
+        count => Err(CliError::Unhealthy { count }),
+    }
+}
+
+pub fn print(health: &Health) {
+    for check in health.checks.iter() {
+        let status = match check.ok {
+            true => "ok",
+            false => "FAIL",
+        };
+        println!("{:<5} {:<14} {}", status, check.name, check.message);
+    }
+}
@@ -0,0 +1,437 @@
+use crate::api::{
+    app::files::{self, UploadInfo},
+    BaseUrl,
+};
+use crate::cli::{self, CliError, CliResult, Secret};
+use crate::config;
+use crate::db;
+use crate::model::{File, FileLock};
+use crate::storage::Storage;
+use crate::util::{
+    arg::Opt,
+    date::UtcDateTime,
+    random::random_ascii,
+    size::{human_size, parse_size},
+    thumbnail,
+};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum FileCommand<'a> {
+    Ls(LsArgs<'a>),
+    Info(&'a str),
+    Rm(&'a str),
+    Expire(&'a str, Expiry<'a>),
+    Add(AddArgs<'a>),
+    Thumbs(ThumbsArgs<'a>),
+}
+
+#[derive(Debug)]
+pub struct LsArgs<'a> {
+    user: Option<&'a str>,
+    expired: bool,
+    larger: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub enum Expiry<'a> {
+    At(&'a str),
+    In(&'a str),
+}
+
+#[derive(Debug)]
+pub struct AddArgs<'a> {
+    path: &'a str,
+    user: &'a str,
+    expire: &'a str,
+    name: Option<&'a str>,
+    password: Option<Secret<'a>>,
+    max_downloads: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub struct ThumbsArgs<'a> {
+    user: Option<&'a str>,
+    force: bool,
+}
+
+// {{{ Arguments
+
+fn key_arg() -> Arg<'static, 'static> {
+    Arg::with_name("KEY")
+        .help("The files key")
+        .required(true)
+        .takes_value(true)
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("file")
+        .about("File subcommand")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("List files")
+                .arg(
+                    Arg::with_name("USER")
+                        .long("user")
+                        .help("Only files of this user")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("EXPIRED")
+                        .long("expired")
+                        .help("Only files past their expiry date"),
+                )
+                .arg(
+                    Arg::with_name("LARGER")
+                        .long("larger")
+                        .help("Only files larger than this, e.g. 100M")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show a file")
+                .arg(key_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Delete a file")
+                .arg(key_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("expire")
+                .about("Change when a file expires")
+                .arg(key_arg())
+                .arg(
+                    Arg::with_name("AT")
+                        .long("at")
+                        .help("UTC date, e.g. 2020-01-31 or '2020-01-31 12:00:00'")
+                        .takes_value(true)
+                        .required_unless("IN"),
+                )
+                .arg(
+                    Arg::with_name("IN")
+                        .long("in")
+                        .help("From now, an expiry code or e.g. 12h, 3d, 2w; 0 expires now")
+                        .takes_value(true)
+                        .conflicts_with("AT"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Import a local file as if it had been uploaded")
+                .arg(
+                    Arg::with_name("PATH")
+                        .help("The file to import, it is copied")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("USER")
+                        .long("user")
+                        .help("The owner of the file")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("EXPIRE")
+                        .long("expire")
+                        .help("One of the configured expiry codes")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("NAME")
+                        .long("name")
+                        .help("The download name, defaults to the file name")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PASSWORD")
+                        .long("password")
+                        .help("Protect the share link with a password, prompted for"),
+                )
+                .arg(
+                    Arg::with_name("PASSWORD_STDIN")
+                        .long("password-stdin")
+                        .help("Protect the share link with the first line of stdin")
+                        .conflicts_with("PASSWORD"),
+                )
+                .arg(
+                    Arg::with_name("MAX_DOWNLOADS")
+                        .long("max-downloads")
+                        .help("Delete the file after this many downloads")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("thumbs")
+                .about("Create missing thumbnails of images")
+                .arg(
+                    Arg::with_name("USER")
+                        .long("user")
+                        .help("Only files of this user")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FORCE")
+                        .long("force")
+                        .help("Recreate existing thumbnails too"),
+                ),
+        )
+}
+
+pub fn parse<'a>(args: &'a ArgMatches) -> FileCommand<'a> {
+    match args.subcommand() {
+        ("ls", Some(options)) => FileCommand::Ls(LsArgs {
+            user: Opt::_opt(options, "USER"),
+            expired: Opt::_flag(options, "EXPIRED"),
+            larger: Opt::_opt(options, "LARGER"),
+        }),
+        ("info", Some(options)) => FileCommand::Info(Opt::_get(options, "KEY")),
+        ("rm", Some(options)) => FileCommand::Rm(Opt::_get(options, "KEY")),
+        ("expire", Some(options)) => FileCommand::Expire(
+            Opt::_get(options, "KEY"),
+            match Opt::_opt(options, "AT") {
+                Some(at) => Expiry::At(at),
+                None => Expiry::In(Opt::_get(options, "IN")),
+            },
+        ),
+        ("add", Some(options)) => FileCommand::Add(AddArgs {
+            path: Opt::_get(options, "PATH"),
+            user: Opt::_get(options, "USER"),
+            expire: Opt::_get(options, "EXPIRE"),
+            name: Opt::_opt(options, "NAME"),
+            password: match (
+                Opt::_flag(options, "PASSWORD"),
+                Opt::_flag(options, "PASSWORD_STDIN"),
+            ) {
+                (_, true) => Some(Secret::Stdin),
+                (true, false) => Some(Secret::Prompt),
+                (false, false) => None,
+            },
+            max_downloads: Opt::_opt(options, "MAX_DOWNLOADS"),
+        }),
+        ("thumbs", Some(options)) => FileCommand::Thumbs(ThumbsArgs {
+            user: Opt::_opt(options, "USER"),
+            force: Opt::_flag(options, "FORCE"),
+        }),
+        _ => unreachable!("bug: clap requires a file subcommand"),
+    }
+}
+
+// }}}
+
+// {{{ Commands
+
+pub fn run(command: FileCommand) -> CliResult {
+    let c = cli::connect();
+    match command {
+        FileCommand::Ls(args) => ls(args, &c),
+        FileCommand::Info(key) => info(key, &*cli::storage()?, &c),
+        FileCommand::Rm(key) => rm(key, &*cli::storage()?, &c),
+        FileCommand::Expire(key, expiry) => expire(key, expiry, &c),
+        FileCommand::Add(args) => add(args, &*cli::storage()?, &c),
+        FileCommand::Thumbs(args) => thumbs(args, &*cli::storage()?, &c),
+    }
+}
+
+pub fn by_key(key: &str, c: &db::Connection) -> Result<File, CliError> {
+    File::by_key(key, c).map_err(|_| CliError::NoSuchFile { key: key.into() })
+}
+
+fn now() -> NaiveDateTime {
+    UtcDateTime(Utc::now()).into()
+}
+
+/// Parse `--in`: a configured expiry code, or a number of hours, days or weeks.
+fn parse_duration(text: &str) -> Option<Duration> {
+    if let Some(duration) = config::get().expiry(text) {
+        return Some(duration);
+    }
+    if text == "0" {
+        return Some(Duration::zero());
+    }
+    let (last, _) = text.char_indices().last()?;
+    let (count, unit) = text.split_at(last);
+    let count = count.parse::<i64>().ok().filter(|&count| count > 0)?;
+    match unit {
+        "h" => Some(Duration::hours(count)),
+        "d" => Some(Duration::days(count)),
+        "w" => Some(Duration::weeks(count)),
+        _ => None,
+    }
+}
+
+/// Parse `--at`: a UTC date or date and time.
+fn parse_date(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms(0, 0, 0))
+        })
+}
+
+fn ls(args: LsArgs, c: &db::Connection) -> CliResult {
+    let user_id = match args.user {
+        Some(name) => Some(cli::user::by_name(name, c)?.id),
+        None => None,
+    };
+    let larger = match args.larger {
+        Some(size) => Some(
+            parse_size(size)
+                .ok_or_else(|| cli::invalid("size", "sizes look like 512, 20M or 5G"))?
+                as i64,
+        ),
+        None => None,
+    };
+    let expired = if args.expired { Some(now()) } else { None };
+
+    println!(
+        "{:<32} {:>5} {:>10} {:>9} {:<19}  {}",
+        "key", "user", "bytes", "downloads", "expires", "name"
+    );
+    for file in File::list(user_id, expired, larger, c)? {
+        println!(
+            "{:<32} {:>5} {:>10} {:>9} {:<19}  {}",
+            file.key,
+            file.user_id,
+            human_size(file.bytes as u64),
+            file.downloads,
+            file.delete_date.format("%Y-%m-%d %H:%M:%S").to_string(),
+            file.val
+        );
+    }
+    Ok(())
+}
+
+fn info(key: &str, storage: &dyn Storage, c: &db::Connection) -> CliResult {
+    let file = by_key(key, c)?;
+    println!("id: {}", file.id);
+    println!("key: {}", file.key);
+    println!("name: {}", file.val);
+    println!("user: {}", file.user_id);
+    println!("bytes: {} ({})", file.bytes, human_size(file.bytes as u64));
+    println!(
+        "type: {}",
+        file.content_type.as_ref().map_or("unknown", String::as_str)
+    );
+    println!("uploaded: {}", file.upload_date);
+    println!("expires: {}", file.delete_date);
+    match file.max_downloads {
+        Some(max) => println!("downloads: {} / {}", file.downloads, max),
+        None => println!("downloads: {}", file.downloads),
+    }
+    println!("protected: {}", file.is_locked());
+    match storage.stat(file.user_id, &file.key)? {
+        Some(stat) => println!("stored: {} bytes", stat.bytes),
+        None => println!("stored: missing"),
+    }
+    if let Some(base) = BaseUrl::configured() {
+        println!("url: {}", base.download_url(&file.key));
+    }
+    Ok(())
+}
+
+fn rm(key: &str, storage: &dyn Storage, c: &db::Connection) -> CliResult {
+    let file = by_key(key, c)?;
+    files::remove(&file, storage, c)?;
+    println!("deleted file: {}", file);
+    Ok(())
+}
+
+fn expire(key: &str, expiry: Expiry, c: &db::Connection) -> CliResult {
+    let mut file = by_key(key, c)?;
+    file.delete_date = match expiry {
+        Expiry::At(at) => {
+            parse_date(at).ok_or_else(|| cli::invalid("date", "dates look like 2020-01-31"))?
+        }
+        Expiry::In(after) => {
+            now()
+                + parse_duration(after)
+                    .ok_or_else(|| cli::invalid("duration", "durations look like w, 12h or 3d"))?
+        }
+    };
+    file.update(c)?;
+    println!("{} expires {}", file, file.delete_date);
+    Ok(())
+}
+
+fn add(args: AddArgs, storage: &dyn Storage, c: &db::Connection) -> CliResult {
+    use std::fs;
+
+    let user = cli::user::by_name(args.user, c)?;
+    let path = Path::new(args.path);
+    if config::get().expiry(args.expire).is_none() {
+        return Err(cli::invalid("expiry code", args.expire));
+    }
+    let name = match args.name {
+        Some(name) => name.to_string(),
+        None => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| cli::invalid("path", args.path))?,
+    };
+    let info = UploadInfo {
+        name: name,
+        meta: args.expire.into(),
+        lock: match args.password {
+            Some(secret) => Some(FileLock::new(&secret.read()?)),
+            None => None,
+        },
+        max_downloads: files::max_downloads(args.max_downloads, None)?,
+    };
+
+    /* Go through the spool like an upload, the original stays in place. */
+    files::create_user_spool(user.id)?;
+    let spooled = files::user_spool(user.id).join(format!("import-{}", random_ascii(16)));
+    let bytes = fs::copy(path, &spooled)?;
+    let file = match files::store_upload(user.id, &info, &spooled, bytes, storage, c) {
+        Ok(file) => file,
+        Err(error) => {
+            if spooled.exists() {
+                fs::remove_file(&spooled)?;
+            }
+            return Err(error.into());
+        }
+    };
+
+    println!("added file: {}", file);
+    if let Some(base) = BaseUrl::configured() {
+        println!("url: {}", base.download_url(&file.key));
+    }
+    if config::get().thumbnails.enabled {
+        if let Err(error) = thumbnail::generate(file.user_id, &file.key, storage) {
+            warn!("no thumbnail for {}: {}", file, error);
+        }
+    }
+    Ok(())
+}
+
+fn thumbs(args: ThumbsArgs, storage: &dyn Storage, c: &db::Connection) -> CliResult {
+    let user_id = match args.user {
+        Some(name) => Some(cli::user::by_name(name, c)?.id),
+        None => None,
+    };
+    let mut created = 0;
+    for file in File::list(user_id, None, None, c)? {
+        if !args.force && thumbnail::path(&file.key).exists() {
+            continue;
+        }
+        match thumbnail::generate(file.user_id, &file.key, storage) {
+            Ok(true) => created += 1,
+            Ok(false) => {}
+            Err(error) => warn!("no thumbnail for {}: {}", file, error),
+        }
+    }
+    println!("created {} thumbnails", created);
+    Ok(())
+}
+
+// }}}
@@ -0,0 +1,114 @@
+use crate::cli::{self, CliError, CliResult};
+use crate::task::fsck::{Fsck, ProblemKind, Repair, Report};
+use crate::util::arg::Opt;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct FsckCommand<'a> {
+    repair: bool,
+    delete_orphans: bool,
+    json: bool,
+    spool_age: &'a str,
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("fsck")
+        .about("Check the database against storage and the spool")
+        .arg(
+            Arg::with_name("REPAIR")
+                .long("repair")
+                .help("Drop records without blobs, fix sizes and clear stale spool files"),
+        )
+        .arg(
+            Arg::with_name("DELETE_ORPHANS")
+                .long("delete-orphans")
+                .help("Also delete blobs without a record")
+                .requires("REPAIR"),
+        )
+        .arg(
+            Arg::with_name("JSON")
+                .long("json")
+                .help("Print the report as JSON"),
+        )
+        .arg(
+            Arg::with_name("SPOOL_AGE")
+                .long("spool-age")
+                .help("Hours after which spool files count as abandoned")
+                .default_value("24")
+                .takes_value(true),
+        )
+}
+
+pub fn parse<'a>(args: &'a ArgMatches) -> FsckCommand<'a> {
+    FsckCommand {
+        repair: Opt::_flag(args, "REPAIR"),
+        delete_orphans: Opt::_flag(args, "DELETE_ORPHANS"),
+        json: Opt::_flag(args, "JSON"),
+        spool_age: Opt::_get(args, "SPOOL_AGE"),
+    }
+}
+
+pub fn run(command: FsckCommand) -> CliResult {
+    let hours: u64 = command
+        .spool_age
+        .parse()
+        .map_err(|_| cli::invalid("spool age", command.spool_age))?;
+    let repair = match command.repair {
+        true => Repair {
+            orphans: command.delete_orphans,
+            ..Repair::safe()
+        },
+        false => Repair::none(),
+    };
+
+    let c = cli::connect();
+    let storage = cli::storage()?;
+    let report = Fsck::new(&*storage, Duration::from_secs(hours * 60 * 60)).run(repair, &c)?;
+
+    if command.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(crate::util::error::Error::from)?
+        );
+    } else {
+        print(&report);
+    }
+
+    match report.remaining() {
+        0 => Ok(()),
+        count => Err(CliError::Unclean { count }),
+    }
+}
+
+fn print(report: &Report) {
+    for problem in &report.problems {
+        let what = match problem.kind {
+            ProblemKind::MissingBlob => "missing blob",
+            ProblemKind::OrphanBlob => "orphan blob",
+            ProblemKind::SizeMismatch => "size mismatch",
+            ProblemKind::StaleSpool => "stale spool",
+            ProblemKind::OrphanThumbnail => "orphan thumbnail",
+        };
+        let target = match (problem.user_id, &problem.key, &problem.path) {
+            (_, _, Some(path)) => path.display().to_string(),
+            (Some(user_id), Some(key), None) => format!("{}/{}", user_id, key),
+            _ => String::from("?"),
+        };
+        let sizes = match (problem.expected, problem.found) {
+            (Some(expected), Some(found)) => format!(" ({} != {} bytes)", expected, found),
+            (None, Some(found)) => format!(" ({} bytes)", found),
+            _ => String::new(),
+        };
+        let repaired = if problem.repaired { " [repaired]" } else { "" };
+        println!("{}: {}{}{}", what, target, sizes, repaired);
+    }
+    println!(
+        "checked {} file(s), {} blob(s), {} spool file(s): {} problem(s), {} left",
+        report.files,
+        report.blobs,
+        report.spool,
+        report.problems.len(),
+        report.remaining()
+    );
+}
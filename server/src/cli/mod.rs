@@ -0,0 +1,131 @@
+pub mod doctor;
+pub mod file;
+pub mod fsck;
+pub mod token;
+pub mod user;
+
+use crate::config;
+use crate::db;
+use crate::storage::{self, SharedStorage};
+use crate::util::error::Error;
+use clap::ArgMatches;
+use std::io::{self, BufRead};
+
+#[derive(Debug, Fail)]
+pub enum CliError {
+    #[fail(display = "no such user: {}", name)]
+    NoSuchUser { name: String },
+    #[fail(display = "no such file: {}", key)]
+    NoSuchFile { key: String },
+    #[fail(display = "invalid {}: {}", arg, message)]
+    InvalidArgument { arg: String, message: String },
+    #[fail(display = "passwords do not match")]
+    PasswordMismatch {},
+    #[fail(display = "password must not be empty")]
+    PasswordEmpty {},
+    #[fail(display = "{} problem(s) left", count)]
+    Unclean { count: usize },
+    #[fail(display = "{} check(s) failed", count)]
+    Unhealthy { count: usize },
+    #[fail(display = "input/output failure: {}", error)]
+    IoError { error: io::Error },
+    #[fail(display = "{}", error)]
+    Error { error: Error },
+}
+
+pub type CliResult = Result<(), CliError>;
+
+impl From<Error> for CliError {
+    fn from(error: Error) -> Self {
+        CliError::Error { error }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(error: io::Error) -> Self {
+        CliError::IoError { error }
+    }
+}
+
+pub fn invalid(arg: &str, message: &str) -> CliError {
+    CliError::InvalidArgument {
+        arg: arg.into(),
+        message: message.into(),
+    }
+}
+
+/// Opens a database connection for a one-off command.
+pub fn connect() -> db::Connection {
+    let pool = db::Connection::pool();
+    db::Connection(pool.get().expect("[cli] could not connect to the database"))
+}
+
+/// Opens the configured storage backend.
+pub fn storage() -> Result<SharedStorage, CliError> {
+    Ok(storage::open(&config::get().storage)?)
+}
+
+/// Prints the error of a failed command and exits with a non-zero status.
+pub fn finish(result: CliResult) {
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+/// Where a command takes a password from.
+pub enum Secret<'a> {
+    /* Given on the command line, visible in shell history and `ps`. */
+    Arg(&'a str),
+    /* The first line of standard input. */
+    Stdin,
+    /* Typed twice on the terminal without echo. */
+    Prompt,
+}
+
+impl<'a> std::fmt::Debug for Secret<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Secret::Arg(_) => write!(f, "Arg([redacted])"),
+            Secret::Stdin => write!(f, "Stdin"),
+            Secret::Prompt => write!(f, "Prompt"),
+        }
+    }
+}
+
+impl<'a> Secret<'a> {
+    pub fn from_args(args: &'a ArgMatches, key: &str, stdin: &str) -> Self {
+        use crate::util::arg::Opt;
+        match Opt::_opt(args, key) {
+            Some(value) => Secret::Arg(value),
+            None if Opt::_flag(args, stdin) => Secret::Stdin,
+            None => Secret::Prompt,
+        }
+    }
+
+    pub fn read(&self) -> Result<String, CliError> {
+        let password = match self {
+            Secret::Arg(value) => {
+                eprintln!("warning: passwords given as arguments end up in your shell history");
+                value.to_string()
+            }
+            Secret::Stdin => {
+                let mut line = String::new();
+                io::stdin().lock().read_line(&mut line)?;
+                line.trim_end_matches(|c| c == '\n' || c == '\r').into()
+            }
+            Secret::Prompt => {
+                let one = rpassword::read_password_from_tty(Some("Password: "))?;
+                let two = rpassword::read_password_from_tty(Some("Repeat password: "))?;
+                if one != two {
+                    return Err(CliError::PasswordMismatch {});
+                }
+                one
+            }
+        };
+        if password.is_empty() {
+            return Err(CliError::PasswordEmpty {});
+        }
+        Ok(password)
+    }
+}
@@ -0,0 +1,143 @@
+use crate::cli::{self, CliResult};
+use crate::db;
+use crate::model::Token;
+use crate::util::{arg::Opt, date::UtcDateTime};
+use chrono::{Duration, NaiveDateTime, Utc};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+#[derive(Debug)]
+pub enum TokenCommand<'a> {
+    List(&'a str),
+    Add(&'a str, &'a str, Option<&'a str>),
+    Revoke(&'a str, &'a str),
+}
+
+// {{{ Arguments
+
+fn user_arg() -> Arg<'static, 'static> {
+    Arg::with_name("USER")
+        .help("The tokens owner")
+        .required(true)
+        .takes_value(true)
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("token")
+        .about("Personal access token subcommand")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List a users tokens")
+                .arg(user_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Create a token and print it once")
+                .arg(user_arg())
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("What the token is used for")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("DAYS")
+                        .long("days")
+                        .help("Days until the token expires, never if omitted")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("revoke")
+                .about("Revoke a token")
+                .arg(user_arg())
+                .arg(
+                    Arg::with_name("ID")
+                        .help("The tokens id")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+}
+
+pub fn parse<'a>(args: &'a ArgMatches) -> TokenCommand<'a> {
+    match args.subcommand() {
+        ("list", Some(options)) => TokenCommand::List(Opt::_get(options, "USER")),
+        ("add", Some(options)) => TokenCommand::Add(
+            Opt::_get(options, "USER"),
+            Opt::_get(options, "NAME"),
+            Opt::_opt(options, "DAYS"),
+        ),
+        ("revoke", Some(options)) => {
+            TokenCommand::Revoke(Opt::_get(options, "USER"), Opt::_get(options, "ID"))
+        }
+        _ => unreachable!("bug: clap requires a token subcommand"),
+    }
+}
+
+// }}}
+
+// {{{ Commands
+
+pub fn run(command: TokenCommand) -> CliResult {
+    let c = cli::connect();
+    match command {
+        TokenCommand::List(user) => list(user, &c),
+        TokenCommand::Add(user, name, days) => add(user, name, days, &c),
+        TokenCommand::Revoke(user, id) => revoke(user, id, &c),
+    }
+}
+
+fn date(date: Option<NaiveDateTime>, none: &str) -> String {
+    match date {
+        Some(date) => date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => none.into(),
+    }
+}
+
+fn list(user: &str, c: &db::Connection) -> CliResult {
+    let user = cli::user::by_name(user, c)?;
+    println!(
+        "{:>5}  {:<24} {:<19}  {:<19}  {}",
+        "id", "name", "created", "expires", "last used"
+    );
+    for token in Token::by_user(user.id, c)? {
+        println!(
+            "{:>5}  {:<24} {:<19}  {:<19}  {}",
+            token.id,
+            token.name,
+            date(Some(token.created_at), "-"),
+            date(token.expires_at, "never"),
+            date(token.last_used, "never")
+        );
+    }
+    Ok(())
+}
+
+fn add(user: &str, name: &str, days: Option<&str>, c: &db::Connection) -> CliResult {
+    let user = cli::user::by_name(user, c)?;
+    let expires_at = match days {
+        Some(days) => match days.parse::<i64>() {
+            Ok(days) if days > 0 => Some(UtcDateTime(Utc::now() + Duration::days(days)).into()),
+            _ => return Err(cli::invalid("days", days)),
+        },
+        None => None,
+    };
+    let (token, secret) = Token::create(user.id, name, expires_at, c)?;
+    println!(
+        "created token {} for {}, it is not shown again:",
+        token.id, user.name
+    );
+    println!("{}", secret);
+    Ok(())
+}
+
+fn revoke(user: &str, id: &str, c: &db::Connection) -> CliResult {
+    let user = cli::user::by_name(user, c)?;
+    let id = id.parse().map_err(|_| cli::invalid("token id", id))?;
+    Token::revoke(id, user.id, c)?;
+    println!("revoked token: {}", id);
+    Ok(())
+}
+
+// }}}
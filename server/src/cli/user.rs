@@ -0,0 +1,479 @@
+use crate::cli::{self, CliError, CliResult, Secret};
+use crate::config;
+use crate::db;
+use crate::model::{Disposal, Lockout, Password, Quota, Removal, Session, Usage, User, UserInsert};
+use crate::util::{
+    arg::Opt,
+    size::{human_size, parse_size},
+};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+#[derive(Debug)]
+pub enum UserCommand<'a> {
+    Add(&'a str, Secret<'a>),
+    Del(&'a str, DelArgs<'a>),
+    List,
+    Show(&'a str),
+    Passwd(&'a str, Secret<'a>, bool),
+    Rename(&'a str, &'a str),
+    Sessions(&'a str, SessionsAction<'a>),
+    Quota(&'a str, QuotaArgs<'a>),
+    Lockouts(&'a str),
+}
+
+#[derive(Debug)]
+pub enum SessionsAction<'a> {
+    List,
+    Revoke(&'a str),
+    RevokeAll,
+}
+
+#[derive(Debug)]
+pub struct DelArgs<'a> {
+    reassign: Option<&'a str>,
+    remove_files: bool,
+    dry_run: bool,
+}
+
+#[derive(Debug)]
+pub struct QuotaArgs<'a> {
+    bytes: Option<&'a str>,
+    files: Option<&'a str>,
+    file_bytes: Option<&'a str>,
+    clear: bool,
+}
+
+// {{{ Arguments
+
+fn name_arg() -> Arg<'static, 'static> {
+    Arg::with_name("NAME")
+        .help("The users name")
+        .required(true)
+        .takes_value(true)
+}
+
+fn pass_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("PASS")
+            .help("The users password, prompted for if omitted")
+            .takes_value(true),
+        Arg::with_name("STDIN")
+            .long("stdin")
+            .help("Read the password from the first line of stdin")
+            .conflicts_with("PASS"),
+    ]
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("user")
+        .about("User subcommand")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Add a user")
+                .arg(name_arg())
+                .args(&pass_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("del")
+                .about("Delete a user with their files, sessions and spool")
+                .arg(name_arg())
+                .arg(
+                    Arg::with_name("REASSIGN")
+                        .long("reassign")
+                        .help("Give the users files to this user instead of removing them")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("REMOVE_FILES")
+                        .long("remove-files")
+                        .help("Remove the users files even if `reassign_to` is configured")
+                        .conflicts_with("REASSIGN"),
+                )
+                .arg(
+                    Arg::with_name("DRY_RUN")
+                        .long("dry-run")
+                        .help("Only report what would be deleted"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("list").about("List users and their usage"))
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Show a users usage, quota and sessions")
+                .arg(name_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("passwd")
+                .about("Change a users password and log them out everywhere")
+                .arg(name_arg())
+                .args(&pass_args())
+                .arg(
+                    Arg::with_name("KEEP_SESSIONS")
+                        .long("keep-sessions")
+                        .help("Do not revoke the users sessions"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rename")
+                .about("Rename a user")
+                .arg(name_arg())
+                .arg(
+                    Arg::with_name("NEW_NAME")
+                        .help("The users new name")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sessions")
+                .about("List or revoke a users sessions")
+                .arg(name_arg())
+                .arg(
+                    Arg::with_name("REVOKE")
+                        .long("revoke")
+                        .help("Revoke the session with this id")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("REVOKE_ALL")
+                        .long("revoke-all")
+                        .help("Revoke all sessions")
+                        .conflicts_with("REVOKE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lockouts")
+                .about("List recent lockouts after failed logins")
+                .arg(
+                    Arg::with_name("LIMIT")
+                        .long("limit")
+                        .help("How many to show")
+                        .default_value("50")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("quota")
+                .about("Show or set a users storage quota")
+                .arg(name_arg())
+                .arg(
+                    Arg::with_name("BYTES")
+                        .long("bytes")
+                        .help("Total size of all files, e.g. 20G")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FILES")
+                        .long("files")
+                        .help("Number of files")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FILE_BYTES")
+                        .long("file-bytes")
+                        .help("Size of a single file, e.g. 2G")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("CLEAR")
+                        .long("clear")
+                        .help("Remove all limits before applying the others"),
+                ),
+        )
+}
+
+pub fn parse<'a>(args: &'a ArgMatches) -> UserCommand<'a> {
+    match args.subcommand() {
+        ("add", Some(options)) => UserCommand::Add(
+            Opt::_get(options, "NAME"),
+            Secret::from_args(options, "PASS", "STDIN"),
+        ),
+        ("del", Some(options)) => UserCommand::Del(
+            Opt::_get(options, "NAME"),
+            DelArgs {
+                reassign: Opt::_opt(options, "REASSIGN"),
+                remove_files: Opt::_flag(options, "REMOVE_FILES"),
+                dry_run: Opt::_flag(options, "DRY_RUN"),
+            },
+        ),
+        ("list", Some(_)) => UserCommand::List,
+        ("show", Some(options)) => UserCommand::Show(Opt::_get(options, "NAME")),
+        ("passwd", Some(options)) => UserCommand::Passwd(
+            Opt::_get(options, "NAME"),
+            Secret::from_args(options, "PASS", "STDIN"),
+            Opt::_flag(options, "KEEP_SESSIONS"),
+        ),
+        ("rename", Some(options)) => {
+            UserCommand::Rename(Opt::_get(options, "NAME"), Opt::_get(options, "NEW_NAME"))
+        }
+        ("sessions", Some(options)) => UserCommand::Sessions(
+            Opt::_get(options, "NAME"),
+            match Opt::_opt(options, "REVOKE") {
+                Some(id) => SessionsAction::Revoke(id),
+                None if Opt::_flag(options, "REVOKE_ALL") => SessionsAction::RevokeAll,
+                None => SessionsAction::List,
+            },
+        ),
+        ("quota", Some(options)) => UserCommand::Quota(
+            Opt::_get(options, "NAME"),
+            QuotaArgs {
+                bytes: Opt::_opt(options, "BYTES"),
+                files: Opt::_opt(options, "FILES"),
+                file_bytes: Opt::_opt(options, "FILE_BYTES"),
+                clear: Opt::_flag(options, "CLEAR"),
+            },
+        ),
+        ("lockouts", Some(options)) => UserCommand::Lockouts(Opt::_get(options, "LIMIT")),
+        _ => unreachable!("bug: clap requires a user subcommand"),
+    }
+}
+
+// }}}
+
+// {{{ Commands
+
+pub fn run(command: UserCommand) -> CliResult {
+    let c = cli::connect();
+    match command {
+        UserCommand::Add(name, secret) => add(name, secret, &c),
+        UserCommand::Del(name, args) => del(name, args, &c),
+        UserCommand::List => list(&c),
+        UserCommand::Show(name) => show(name, &c),
+        UserCommand::Passwd(name, secret, keep) => passwd(name, secret, keep, &c),
+        UserCommand::Rename(name, new_name) => rename(name, new_name, &c),
+        UserCommand::Sessions(name, action) => sessions(name, action, &c),
+        UserCommand::Quota(name, args) => quota(name, args, &c),
+        UserCommand::Lockouts(limit) => lockouts(limit, &c),
+    }
+}
+
+pub fn by_name(name: &str, c: &db::Connection) -> Result<User, CliError> {
+    User::by_name(name, c).map_err(|_| CliError::NoSuchUser { name: name.into() })
+}
+
+fn limit(limit: Option<i64>) -> String {
+    match limit {
+        Some(limit) => human_size(limit as u64),
+        None => String::from("unlimited"),
+    }
+}
+
+fn add(name: &str, secret: Secret, c: &db::Connection) -> CliResult {
+    let password = secret.read()?;
+    let (user, _) = User::create(&UserInsert { name: name.into() }, &password, c)?;
+    println!("added user: {} (id {})", user.name, user.id);
+    Ok(())
+}
+
+fn del(name: &str, args: DelArgs, c: &db::Connection) -> CliResult {
+    let user = by_name(name, c)?;
+    let config = config::get();
+    let heir = match args.reassign {
+        Some(heir) => Some(heir),
+        None if args.remove_files => None,
+        None => config.reassign_to.as_ref().map(String::as_str),
+    };
+    let disposal = match heir {
+        Some(heir) => {
+            let heir = by_name(heir, c)?;
+            if heir.id == user.id {
+                return Err(cli::invalid(
+                    "reassign",
+                    "a user can't inherit their own files",
+                ));
+            }
+            Disposal::Reassign(heir)
+        }
+        None => Disposal::Remove,
+    };
+
+    let removal = Removal::plan(user, disposal, c)?;
+    let verb = if args.dry_run { "would" } else { "will" };
+    match removal.disposal {
+        Disposal::Remove => println!(
+            "{} remove {} file(s), {}",
+            verb,
+            removal.files.len(),
+            human_size(removal.bytes() as u64)
+        ),
+        Disposal::Reassign(ref heir) => println!(
+            "{} give {} file(s), {} to {}",
+            verb,
+            removal.files.len(),
+            human_size(removal.bytes() as u64),
+            heir.name
+        ),
+    }
+    for file in &removal.files {
+        println!("  {} {}", file.key, file.val);
+    }
+    println!("{} revoke {} session(s)", verb, removal.sessions);
+    println!("{} remove {} spool file(s)", verb, removal.spool.len());
+    for path in &removal.spool {
+        println!("  {}", path.display());
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    removal.execute(&*cli::storage()?, c)?;
+    println!("deleted user: {}", name);
+    Ok(())
+}
+
+fn list(c: &db::Connection) -> CliResult {
+    println!("{:>5}  {:<24} {:>7} {:>10}", "id", "name", "files", "bytes");
+    for user in User::all(c)? {
+        let usage = Usage::of(user.id, c)?;
+        println!(
+            "{:>5}  {:<24} {:>7} {:>10}",
+            user.id,
+            user.name,
+            usage.files,
+            human_size(usage.bytes as u64)
+        );
+    }
+    Ok(())
+}
+
+fn show(name: &str, c: &db::Connection) -> CliResult {
+    let user = by_name(name, c)?;
+    let usage = Usage::of(user.id, c)?;
+    let quota = Quota::by_user(user.id, c)?;
+    let sessions = Session::by_user(user.id, c)?;
+    println!("id: {}", user.id);
+    println!("name: {}", user.name);
+    print_usage(&usage, &quota);
+    println!("sessions: {}", sessions.len());
+    Ok(())
+}
+
+fn passwd(name: &str, secret: Secret, keep_sessions: bool, c: &db::Connection) -> CliResult {
+    let user = by_name(name, c)?;
+    let password = secret.read()?;
+    Password::set(user.id, &password, c)?;
+    println!("changed password: {}", name);
+    if !keep_sessions {
+        let revoked = Session::revoke_all(user.id, c)?;
+        println!("revoked sessions: {}", revoked);
+    }
+    Ok(())
+}
+
+fn rename(name: &str, new_name: &str, c: &db::Connection) -> CliResult {
+    let mut user = by_name(name, c)?;
+    user.rename(new_name, c)?;
+    println!("renamed user: {} -> {}", name, new_name);
+    Ok(())
+}
+
+fn sessions(name: &str, action: SessionsAction, c: &db::Connection) -> CliResult {
+    let user = by_name(name, c)?;
+    match action {
+        SessionsAction::List => {
+            println!(
+                "{:>5}  {:<19}  {:<19}  {:<15}  {}",
+                "id", "created", "last seen", "ip", "user agent"
+            );
+            for session in Session::by_user(user.id, c)? {
+                println!(
+                    "{:>5}  {:<19}  {:<19}  {:<15}  {}",
+                    session.id,
+                    session.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    session.last_seen.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    session.ip.as_ref().map(String::as_str).unwrap_or("-"),
+                    session
+                        .user_agent
+                        .as_ref()
+                        .map(String::as_str)
+                        .unwrap_or("-")
+                );
+            }
+        }
+        SessionsAction::Revoke(id) => {
+            let id = id.parse().map_err(|_| cli::invalid("session id", id))?;
+            match Session::revoke(id, user.id, c)? {
+                0 => println!("no such session: {}", id),
+                _ => println!("revoked session: {}", id),
+            }
+        }
+        SessionsAction::RevokeAll => {
+            let revoked = Session::revoke_all(user.id, c)?;
+            println!("revoked sessions: {}", revoked);
+        }
+    }
+    Ok(())
+}
+
+fn lockouts(limit: &str, c: &db::Connection) -> CliResult {
+    let limit = limit.parse().map_err(|_| cli::invalid("limit", limit))?;
+    println!(
+        "{:<19}  {:<19}  {:<7} {:>8}  {}",
+        "locked", "until", "kind", "failures", "subject"
+    );
+    for lockout in Lockout::recent(limit, c)? {
+        println!(
+            "{:<19}  {:<19}  {:<7} {:>8}  {}",
+            lockout.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            lockout.locked_until.format("%Y-%m-%d %H:%M:%S").to_string(),
+            lockout.kind,
+            lockout.failures,
+            lockout.subject
+        );
+    }
+    Ok(())
+}
+
+fn quota(name: &str, args: QuotaArgs, c: &db::Connection) -> CliResult {
+    let user = by_name(name, c)?;
+    let size = |arg: Option<&str>| match arg {
+        Some(arg) => parse_size(arg)
+            .map(|size| Some(size as i64))
+            .ok_or_else(|| cli::invalid("size", "sizes look like 512, 20M or 5G")),
+        None => Ok(None),
+    };
+
+    let mut quota = Quota::by_user(user.id, c)?;
+    if args.clear {
+        quota = Quota::unlimited(user.id);
+    }
+    if let Some(bytes) = size(args.bytes)? {
+        quota.max_bytes = Some(bytes);
+    }
+    if let Some(files) = args.files {
+        quota.max_files = Some(
+            files
+                .parse()
+                .map_err(|_| cli::invalid("file count", files))?,
+        );
+    }
+    if let Some(bytes) = size(args.file_bytes)? {
+        quota.max_file_bytes = Some(bytes);
+    }
+    if args.clear || args.bytes.is_some() || args.files.is_some() || args.file_bytes.is_some() {
+        quota.save(c)?;
+        println!("updated quota: {}", name);
+    }
+
+    print_usage(&Usage::of(user.id, c)?, &quota);
+    Ok(())
+}
+
+fn print_usage(usage: &Usage, quota: &Quota) {
+    println!(
+        "bytes: {} / {}",
+        human_size(usage.bytes as u64),
+        limit(quota.max_bytes)
+    );
+    println!(
+        "files: {} / {}",
+        usage.files,
+        quota
+            .max_files
+            .map(|files| files.to_string())
+            .unwrap_or_else(|| String::from("unlimited"))
+    );
+    println!("max file size: {}", limit(quota.max_file_bytes));
+}
+
+// }}}
@@ -0,0 +1,424 @@
+use crate::util::secret::Secret;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::{env, fs};
+
+pub const CONFIG_FILE: &'static str = "flink.toml";
+
+lazy_static! {
+    static ref CONFIG: RwLock<Arc<Config>> = RwLock::new(Arc::new(Config::default()));
+}
+
+/// Install the configuration used by the rest of the service.
+pub fn init(config: Config) {
+    *CONFIG.write().expect("[config] lock poisoned") = Arc::new(config);
+}
+
+/// The currently active configuration.
+pub fn get() -> Arc<Config> {
+    CONFIG.read().expect("[config] lock poisoned").clone()
+}
+
+#[derive(Debug, Fail)]
+pub enum ConfigError {
+    #[fail(display = "failed to read '{}': {}", path, error)]
+    IoError { path: String, error: std::io::Error },
+    #[fail(display = "failed to parse '{}': {}", path, error)]
+    ParseError {
+        path: String,
+        error: toml::de::Error,
+    },
+    #[fail(display = "invalid value for {}: '{}'", key, val)]
+    EnvError { key: String, val: String },
+    #[fail(display = "invalid {}: {}", key, message)]
+    InvalidValue { key: String, message: String },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Local,
+    S3,
+}
+
+/// The header a trusted proxy puts the client address in. Only that one is
+/// read; the other may hold whatever the client sent.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientIpHeader {
+    XForwardedFor,
+    XRealIp,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct S3Config {
+    /// Endpoint including the scheme, e.g. `http://127.0.0.1:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "S3Config::default_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: Secret,
+    /// Prepended to every object name.
+    #[serde(default)]
+    pub prefix: String,
+}
+
+impl S3Config {
+    fn default_region() -> String {
+        String::from("us-east-1")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: StorageKind,
+    pub s3: Option<S3Config>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageKind::Local,
+            s3: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ThumbnailConfig {
+    pub enabled: bool,
+    /// Longest side of a thumbnail in pixels.
+    pub size: u32,
+    /// Larger images aren't decoded.
+    pub max_bytes: u64,
+    /// Neither are images with more pixels than this.
+    pub max_pixels: u64,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            size: 256,
+            max_bytes: 50 * 1024 * 1024,
+            max_pixels: 50 * 1000 * 1000,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// Seconds between counts of the files and bytes per user.
+    pub interval: u64,
+    /// Clients allowed to scrape `/metrics`.
+    pub allow: Vec<IpAddr>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 60,
+            allow: vec![
+                IpAddr::from([127, 0, 0, 1]),
+                IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]),
+            ],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Levels per module, e.g. `info,flink::storage=debug,hyper=warn`.
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: String::from("info,hyper=warn"),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogConfig {
+    fn check(&self) -> Result<(), ConfigError> {
+        crate::util::logging::Filter::parse(&self.filter)
+            .map(|_| ())
+            .map_err(|message| ConfigError::InvalidValue {
+                key: String::from("log.filter"),
+                message,
+            })
+    }
+}
+
+/// Cost of the argon2id password hash, and the pepper mixed into it.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PasswordConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// Secret kept out of the database; changing it invalidates every password.
+    pub pepper: Option<Secret>,
+    /// File holding the pepper, read at startup instead of `pepper`.
+    pub pepper_file: Option<PathBuf>,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+            pepper: None,
+            pepper_file: None,
+        }
+    }
+}
+
+impl PasswordConfig {
+    fn load_pepper(&mut self) -> Result<(), ConfigError> {
+        if let Some(ref path) = self.pepper_file {
+            let pepper = fs::read_to_string(path).map_err(|error| ConfigError::IoError {
+                path: path.display().to_string(),
+                error,
+            })?;
+            self.pepper = Some(Secret::new(
+                pepper.trim_end_matches(|c| c == '\n' || c == '\r'),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| ConfigError::InvalidValue {
+            key: String::from("password"),
+            message: message.into(),
+        };
+        if self.iterations < 1 || self.parallelism < 1 {
+            return Err(invalid("iterations and parallelism must be at least 1"));
+        }
+        if self.memory_kib < 8 * self.parallelism {
+            return Err(invalid("memory_kib must be at least 8 * parallelism"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Data directory holding the `store` and `spool` directories.
+    pub dir: PathBuf,
+    pub database_url: String,
+    pub pool_size: u32,
+    /// Maximum size of a single upload in bytes.
+    pub size_limit: u64,
+    /// Length of generated file keys.
+    pub key_length: usize,
+    /// Seconds between sweeps for expired files and sessions.
+    pub reap_interval: u64,
+    /// Expiry codes accepted on upload, in days.
+    pub expiry: BTreeMap<String, i64>,
+    /// Public origin for generated links, e.g. `https://flink.com`.
+    pub public_url: Option<String>,
+    /// Peers whose `X-Forwarded-*` and `X-Real-IP` headers are honoured.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Where those peers put the client address.
+    pub client_ip_header: ClientIpHeader,
+    pub storage: StorageConfig,
+    /// Bytes that have to stay free below `dir` to accept uploads.
+    pub min_free_space: u64,
+    /// Wrong share link passwords a client may try per window.
+    pub unlock_attempts: u32,
+    /// Seconds a client is locked out after too many wrong passwords.
+    pub unlock_window: u64,
+    /// Failed logins per username before it is locked out.
+    pub login_attempts: u32,
+    /// Failed logins per client address before it is locked out.
+    pub login_ip_attempts: u32,
+    /// Seconds a lockout lasts, and after which failures are forgotten.
+    pub login_lockout: u64,
+    /// User who inherits the files of deleted users; they are removed if unset.
+    pub reassign_to: Option<String>,
+    /// Seconds after login a session ends, however active it is.
+    pub session_lifetime: u64,
+    /// Seconds of inactivity after which a session ends.
+    pub session_idle: u64,
+    pub password: PasswordConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig,
+    pub thumbnails: ThumbnailConfig,
+}
+
+/// Where the data lives when neither `flink.toml` nor `FLINK_DIR` say.
+const DEFAULT_DIR: &'static str = "/var/lib/flink";
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut expiry = BTreeMap::new();
+        expiry.insert(String::from("d"), 1);
+        expiry.insert(String::from("w"), 7);
+        expiry.insert(String::from("m"), 7 * 4);
+        expiry.insert(String::from("q"), 7 * 4 * 3);
+        expiry.insert(String::from("y"), 7 * 4 * 12);
+        Self {
+            dir: PathBuf::from(DEFAULT_DIR),
+            database_url: format!("{}/db.sqlite", DEFAULT_DIR),
+            pool_size: 8,
+            size_limit: 5368709120,
+            key_length: 32,
+            reap_interval: 300,
+            expiry,
+            public_url: None,
+            trusted_proxies: Vec::new(),
+            client_ip_header: ClientIpHeader::XForwardedFor,
+            storage: StorageConfig::default(),
+            min_free_space: 1024 * 1024 * 1024,
+            unlock_attempts: 5,
+            unlock_window: 15 * 60,
+            login_attempts: 5,
+            login_ip_attempts: 20,
+            login_lockout: 15 * 60,
+            reassign_to: None,
+            session_lifetime: 30 * 24 * 60 * 60,
+            session_idle: 7 * 24 * 60 * 60,
+            password: PasswordConfig::default(),
+            log: LogConfig::default(),
+            metrics: MetricsConfig::default(),
+            thumbnails: ThumbnailConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Read the configuration file at `path` and apply environment overrides.
+    /// A missing file is only an error if it was asked for explicitly.
+    pub fn load(path: &Path, required: bool) -> Result<Self, ConfigError> {
+        let display = path.display().to_string();
+        let mut config = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|error| ConfigError::ParseError {
+                path: display,
+                error,
+            })?,
+            Err(ref error) if !required && error.kind() == std::io::ErrorKind::NotFound => {
+                warn!("{} not found, using defaults", display);
+                Self::default()
+            }
+            Err(error) => {
+                return Err(ConfigError::IoError {
+                    path: display,
+                    error,
+                })
+            }
+        };
+        config.apply_env()?;
+        config.password.load_pepper()?;
+        config.password.check()?;
+        config.log.check()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(val) = Self::env("FLINK_DIR") {
+            self.dir = PathBuf::from(val);
+        }
+        if let Some(val) = Self::env("FLINK_DATABASE_URL").or_else(|| Self::env("DATABASE_URL")) {
+            self.database_url = val;
+        }
+        if let Some(val) = Self::env("FLINK_PUBLIC_URL") {
+            self.public_url = Some(val);
+        }
+        if let Some(val) = Self::env("FLINK_STORAGE") {
+            self.storage.backend = match val.as_str() {
+                "local" => StorageKind::Local,
+                "s3" => StorageKind::S3,
+                _ => {
+                    return Err(ConfigError::EnvError {
+                        key: String::from("FLINK_STORAGE"),
+                        val,
+                    })
+                }
+            };
+        }
+        if let Some(val) = Self::env("FLINK_PASSWORD_PEPPER") {
+            self.password.pepper = Some(val.into());
+        }
+        if let Some(val) = Self::env("FLINK_PASSWORD_PEPPER_FILE") {
+            self.password.pepper_file = Some(PathBuf::from(val));
+        }
+        if let Some(ref mut s3) = self.storage.s3 {
+            if let Some(val) = Self::env("FLINK_S3_ACCESS_KEY") {
+                s3.access_key = val;
+            }
+            if let Some(val) = Self::env("FLINK_S3_SECRET_KEY") {
+                s3.secret_key = val.into();
+            }
+        }
+        if let Some(val) = Self::env("FLINK_LOG") {
+            self.log.filter = val;
+        }
+        if let Some(val) = Self::env("FLINK_LOG_FORMAT") {
+            self.log.format = match val.as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => {
+                    return Err(ConfigError::EnvError {
+                        key: String::from("FLINK_LOG_FORMAT"),
+                        val,
+                    })
+                }
+            };
+        }
+        Self::env_parse("FLINK_POOL_SIZE", &mut self.pool_size)?;
+        Self::env_parse("FLINK_SIZE_LIMIT", &mut self.size_limit)?;
+        Self::env_parse("FLINK_KEY_LENGTH", &mut self.key_length)?;
+        Self::env_parse("FLINK_REAP_INTERVAL", &mut self.reap_interval)?;
+        Self::env_parse("FLINK_MIN_FREE_SPACE", &mut self.min_free_space)?;
+        Self::env_parse("FLINK_SESSION_LIFETIME", &mut self.session_lifetime)?;
+        Self::env_parse("FLINK_SESSION_IDLE", &mut self.session_idle)?;
+        Self::env_parse("FLINK_METRICS", &mut self.metrics.enabled)?;
+        Self::env_parse("FLINK_METRICS_INTERVAL", &mut self.metrics.interval)?;
+        Self::env_parse("FLINK_THUMBNAILS", &mut self.thumbnails.enabled)?;
+        Ok(())
+    }
+
+    fn env(key: &str) -> Option<String> {
+        env::var(key).ok()
+    }
+
+    fn env_parse<T: std::str::FromStr>(key: &str, target: &mut T) -> Result<(), ConfigError> {
+        if let Some(val) = Self::env(key) {
+            *target = val.parse().map_err(|_| ConfigError::EnvError {
+                key: key.into(),
+                val: val.clone(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Look up the lifetime of an expiry code like `"w"`.
+    pub fn expiry(&self, code: &str) -> Option<chrono::Duration> {
+        self.expiry
+            .get(code)
+            .map(|days| chrono::Duration::days(*days))
+    }
+}
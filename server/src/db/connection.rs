@@ -1,36 +1,40 @@
 use std::ops::Deref;
 
 use diesel::prelude::{RunQueryDsl, SqliteConnection};
-use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection};
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, Pool, PooledConnection};
 
+use crate::config;
 use rocket::http::Status;
 use rocket::request::{self, FromRequest};
 use rocket::{Outcome, Request, State};
 
-pub const DATABASE_URL: &'static str = env!("DATABASE_URL");
-
 pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
 pub struct Connection(pub PooledConnection<ConnectionManager<SqliteConnection>>);
 
 #[derive(Debug)]
 struct SqliteConnectionCustomizer();
-impl<C: diesel::Connection, E> CustomizeConnection<C, E> for SqliteConnectionCustomizer {
-    fn on_acquire(&self, connection: &mut C) -> Result<(), E> {
-        diesel::dsl::sql_query(format!("PRAGMA foreign_keys = ON"))
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for SqliteConnectionCustomizer {
+    fn on_acquire(&self, connection: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        diesel::dsl::sql_query("PRAGMA foreign_keys = ON")
             .execute(connection)
-            .expect("Pragma Error");
-        Ok(())
+            .map(|_| ())
+            .map_err(r2d2::Error::QueryError)
     }
 }
 
 impl Connection {
     pub fn pool() -> SqlitePool {
-        let manager = ConnectionManager::<SqliteConnection>::new(DATABASE_URL);
+        Self::try_pool().expect("[database] error building connection pool")
+    }
+
+    /// The pool, or why no connection could be opened.
+    pub fn try_pool() -> Result<SqlitePool, r2d2::PoolError> {
+        let config = config::get();
+        let manager = ConnectionManager::<SqliteConnection>::new(config.database_url.as_str());
         Pool::builder()
-            .max_size(8)
+            .max_size(config.pool_size)
             .connection_customizer(box SqliteConnectionCustomizer {})
             .build(manager)
-            .expect("[database] error building connection pool")
     }
 }
 
@@ -50,11 +54,11 @@ impl<'a, 'r> FromRequest<'a, 'r> for Connection {
         let pool = request.guard::<State<SqlitePool>>()?;
         match pool.get() {
             Ok(c) => {
-                println!("[database] acquired connection");
+                trace!("acquired connection");
                 Outcome::Success(Connection(c))
             }
             Err(_) => {
-                println!("[database] failed to connect");
+                error!("failed to connect");
                 Outcome::Failure((Status::ServiceUnavailable, ()))
             }
         }
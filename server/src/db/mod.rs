@@ -2,3 +2,6 @@ pub mod connection;
 pub use self::connection::Connection;
 
 pub mod schema;
+
+/// The newest migration this build expects to find applied.
+pub const SCHEMA_VERSION: &'static str = "20261018000008";
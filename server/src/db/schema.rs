@@ -8,14 +8,40 @@ table! {
         delete_date -> Timestamp,
         downloads -> Integer,
         bytes -> BigInt,
+        password_hash -> Nullable<Binary>,
+        password_salt -> Nullable<Text>,
+        max_downloads -> Nullable<Integer>,
+        password_phc -> Nullable<Text>,
+        content_type -> Nullable<Text>,
+    }
+}
+
+table! {
+    lockouts (id) {
+        id -> Integer,
+        kind -> Text,
+        subject -> Text,
+        failures -> Integer,
+        created_at -> Timestamp,
+        locked_until -> Timestamp,
     }
 }
 
 table! {
     passwords (user_id) {
         user_id -> Integer,
-        hash -> Binary,
-        salt -> Text,
+        hash -> Nullable<Binary>,
+        salt -> Nullable<Text>,
+        phc -> Nullable<Text>,
+    }
+}
+
+table! {
+    quotas (user_id) {
+        user_id -> Integer,
+        max_bytes -> Nullable<BigInt>,
+        max_files -> Nullable<Integer>,
+        max_file_bytes -> Nullable<BigInt>,
     }
 }
 
@@ -24,6 +50,22 @@ table! {
         id -> Integer,
         user_id -> Integer,
         token -> Text,
+        created_at -> Timestamp,
+        last_seen -> Timestamp,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
+    }
+}
+
+table! {
+    tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        hash -> Binary,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        last_used -> Nullable<Timestamp>,
     }
 }
 
@@ -36,6 +78,8 @@ table! {
 
 joinable!(files -> users (user_id));
 joinable!(passwords -> users (user_id));
+joinable!(quotas -> users (user_id));
 joinable!(sessions -> users (user_id));
+joinable!(tokens -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(files, passwords, sessions, users,);
+allow_tables_to_appear_in_same_query!(files, lockouts, passwords, quotas, sessions, tokens, users,);
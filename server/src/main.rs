@@ -10,49 +10,184 @@ extern crate diesel;
 extern crate serde;
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+extern crate argon2;
 extern crate argon2rs;
 extern crate base64;
 extern crate chrono;
 extern crate clap;
+extern crate fs2;
+extern crate hex;
+extern crate hmac;
+extern crate image;
 extern crate multipart;
+extern crate reqwest;
+extern crate rpassword;
+extern crate sha2;
+extern crate toml;
 
 pub mod api;
+pub mod cli;
+pub mod config;
 pub mod db;
 pub mod model;
 pub mod site;
+pub mod storage;
+pub mod task;
 pub mod util;
 
-#[derive(Debug)]
-enum UserCommand<'a> {
-    Add(&'a str, &'a str),
-    Del(&'a str),
-}
-
 #[derive(Debug)]
 enum Command<'a> {
-    User(UserCommand<'a>),
+    User(cli::user::UserCommand<'a>),
+    File(cli::file::FileCommand<'a>),
+    Fsck(cli::fsck::FsckCommand<'a>),
+    Token(cli::token::TokenCommand<'a>),
+    Doctor(cli::doctor::DoctorCommand),
     Run,
 }
 
 fn launch_rocket() {
+    use std::time::Duration;
+
+    let config = config::get();
+    let pool = match db::Connection::try_pool() {
+        Ok(pool) => pool,
+        Err(error) => {
+            error!("refusing to start, cannot open the database: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let health = task::Health::check(&pool);
+    if !health.ok() {
+        for check in health.failed() {
+            error!("{} check failed: {}", check.name, check.message);
+        }
+        error!("refusing to start, run `flink doctor` for details");
+        std::process::exit(1);
+    }
+    let storage = match storage::open(&config.storage) {
+        Ok(storage) => storage,
+        Err(error) => {
+            error!("refusing to start, cannot open storage: {}", error);
+            std::process::exit(1);
+        }
+    };
+    task::Reaper::new(
+        pool.clone(),
+        storage.clone(),
+        Duration::from_secs(config.reap_interval),
+    )
+    .spawn();
+    if config.metrics.enabled {
+        task::UsageSampler::new(pool.clone(), Duration::from_secs(config.metrics.interval)).spawn();
+    }
+
+    /* Rocket logs through our logger, which handles colors and levels. */
+    std::env::set_var("ROCKET_CLI_COLORS", "off");
     rocket::ignite()
-        .manage(db::Connection::pool())
+        .attach(util::logging::RequestIds)
+        .attach(util::metrics::RequestMetrics)
+        .manage(pool)
+        .manage(task::Thumbnailer::spawn(storage.clone()))
+        .manage(storage)
+        .manage(api::app::files::UnlockThrottle(
+            util::throttle::Throttle::new(
+                config.unlock_attempts,
+                Duration::from_secs(config.unlock_window),
+            ),
+        ))
+        .manage(api::authentication::LoginThrottle {
+            ip: util::throttle::Backoff::new(
+                config.login_ip_attempts,
+                Duration::from_secs(config.login_lockout),
+            ),
+            account: util::throttle::Backoff::new(
+                config.login_attempts,
+                Duration::from_secs(config.login_lockout),
+            ),
+            lockout: Duration::from_secs(config.login_lockout),
+        })
         // auth module api
         .mount("/api/auth/login", routes![api::authentication::login_http])
         .mount("/api/auth/login", routes![api::authentication::login_json])
         .mount("/api/auth/login", routes![api::authentication::logout])
         .mount("/api/auth/login", routes![api::authentication::query])
+        .mount(
+            "/api/auth/sessions",
+            routes![api::authentication::sessions::query],
+        )
+        .mount(
+            "/api/auth/sessions",
+            routes![api::authentication::sessions::query_forbidden],
+        )
+        .mount(
+            "/api/auth/sessions",
+            routes![api::authentication::sessions::revoke],
+        )
+        .mount(
+            "/api/auth/sessions",
+            routes![api::authentication::sessions::revoke_others],
+        )
+        .mount(
+            "/api/auth/tokens",
+            routes![api::authentication::tokens::query],
+        )
+        .mount(
+            "/api/auth/tokens",
+            routes![api::authentication::tokens::query_forbidden],
+        )
+        .mount(
+            "/api/auth/tokens",
+            routes![api::authentication::tokens::create],
+        )
+        .mount(
+            "/api/auth/tokens",
+            routes![api::authentication::tokens::create_forbidden],
+        )
+        .mount(
+            "/api/auth/tokens",
+            routes![api::authentication::tokens::revoke],
+        )
+        .mount(
+            "/api/auth/tokens",
+            routes![api::authentication::tokens::revoke_forbidden],
+        )
+        // user module api
+        .mount("/api/user/quota", routes![api::user::quota::query])
+        .mount(
+            "/api/user/quota",
+            routes![api::user::quota::query_forbidden],
+        )
         // file module api
         .mount("/api/file", routes![api::app::files::upload_http])
         .mount("/api/file", routes![api::app::files::upload_forbidden])
         .mount("/api/file", routes![api::app::files::delete])
         .mount("/api/file", routes![api::app::files::query])
         .mount("/api/file", routes![api::app::files::query_forbidden])
+        .mount("/api/file", routes![api::app::files::password])
+        // resumable uploads
+        .mount("/api/file/tus", routes![api::app::tus::options])
+        .mount("/api/file/tus", routes![api::app::tus::create])
+        .mount("/api/file/tus", routes![api::app::tus::offset])
+        .mount("/api/file/tus", routes![api::app::tus::append])
+        .mount("/api/file/tus", routes![api::app::tus::terminate])
+        // operations
+        .mount("/", routes![api::metrics::metrics])
+        .mount("/", routes![api::health::healthz])
+        .mount("/", routes![api::health::readyz])
         // site
         .mount("/", routes![site::files])
         .mount("/", routes![site::index])
         // file lookup mounted here for shorter urls
         .mount("/f", routes![api::app::files::lookup])
+        .mount("/f", routes![api::app::files::lookup_head])
+        .mount("/f", routes![api::app::files::lookup_unlock])
+        .mount("/f", routes![api::app::files::thumb])
+        .mount("/f", routes![api::app::files::view])
+        .mount("/f", routes![api::app::files::view_unlock])
         .register(catchers![
             site::json_401,
             site::json_403,
@@ -62,79 +197,58 @@ fn launch_rocket() {
         .launch();
 }
 
-fn add_user(name: &str, password: &str) {
-    println!("add user: {}", name);
-    use model::{User, UserInsert};
-    let pool = db::Connection::pool();
-    let connection = db::Connection(pool.get().unwrap());
-    User::create(&UserInsert { name: name.into() }, password, &connection).unwrap();
-    println!("success");
-}
-
-fn del_user(name: &str) {
-    println!("del user: {}", name);
-    use model::User;
-    let pool = db::Connection::pool();
-    let connection = db::Connection(pool.get().unwrap());
-    if let Ok(user) = User::by_name(name, &connection) {
-        User::delete(user.id, &connection).unwrap();
-        println!("success");
-    } else {
-        println!("no such user: {}", name);
-    }
-}
-
 fn main() {
     use clap::{App, Arg, SubCommand};
-    use util::arg::Opt;
+    use std::path::Path;
 
     let args = App::new("flink")
         .version("0.1")
         .about("Self-Hosted File-Uploader")
         .author("Felix V.")
-        .subcommand(
-            SubCommand::with_name("user")
-                .about("User subcommand")
-                .subcommand(
-                    SubCommand::with_name("add")
-                        .about("Add a user")
-                        .arg(
-                            Arg::with_name("NAME")
-                                .help("The users name")
-                                .required(true)
-                                .takes_value(true),
-                        )
-                        .arg(
-                            Arg::with_name("PASS")
-                                .help("The users password")
-                                .required(true)
-                                .takes_value(true),
-                        ),
-                )
-                .subcommand(
-                    SubCommand::with_name("del").about("Delete a user").arg(
-                        Arg::with_name("NAME")
-                            .help("The users name")
-                            .required(true)
-                            .takes_value(true),
-                    ),
-                ),
+        .arg(
+            Arg::with_name("CONFIG")
+                .long("config")
+                .short("c")
+                .help("Path to the configuration file")
+                .takes_value(true),
         )
+        .subcommand(cli::user::subcommand())
+        .subcommand(cli::file::subcommand())
+        .subcommand(cli::fsck::subcommand())
+        .subcommand(cli::token::subcommand())
+        .subcommand(cli::doctor::subcommand())
         .subcommand(SubCommand::with_name("run").about("Run the service"))
         .get_matches();
 
-    let oo;
+    util::logging::init();
+    let config = match args.value_of("CONFIG") {
+        Some(path) => config::Config::load(Path::new(path), true),
+        None => config::Config::load(Path::new(config::CONFIG_FILE), false),
+    };
+    match config {
+        Ok(config) => {
+            if let Err(message) = util::logging::configure(&config.log) {
+                error!("invalid log filter: {}", message);
+            }
+            config::init(config)
+        }
+        Err(error) => {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+    }
+
     let command = {
         if let Some(options) = args.subcommand_matches("user") {
-            if let Some(options) = options.subcommand_matches("add") {
-                oo = Opt::new(options);
-                Command::User(UserCommand::Add(oo.get("NAME"), oo.get("PASS")))
-            } else if let Some(options) = options.subcommand_matches("del") {
-                oo = Opt::new(options);
-                Command::User(UserCommand::Del(oo.get("NAME")))
-            } else {
-                panic!()
-            }
+            Command::User(cli::user::parse(options))
+        } else if let Some(options) = args.subcommand_matches("file") {
+            Command::File(cli::file::parse(options))
+        } else if let Some(options) = args.subcommand_matches("fsck") {
+            Command::Fsck(cli::fsck::parse(options))
+        } else if let Some(options) = args.subcommand_matches("token") {
+            Command::Token(cli::token::parse(options))
+        } else if let Some(options) = args.subcommand_matches("doctor") {
+            Command::Doctor(cli::doctor::parse(options))
         } else if let Some(_options) = args.subcommand_matches("run") {
             Command::Run
         } else {
@@ -146,13 +260,20 @@ fn main() {
         Command::Run => {
             launch_rocket();
         }
-        Command::User(subcommand) => match subcommand {
-            UserCommand::Add(name, password) => {
-                add_user(name, password);
-            }
-            UserCommand::Del(name) => {
-                del_user(name);
-            }
-        },
+        Command::User(subcommand) => {
+            cli::finish(cli::user::run(subcommand));
+        }
+        Command::File(subcommand) => {
+            cli::finish(cli::file::run(subcommand));
+        }
+        Command::Fsck(subcommand) => {
+            cli::finish(cli::fsck::run(subcommand));
+        }
+        Command::Token(subcommand) => {
+            cli::finish(cli::token::run(subcommand));
+        }
+        Command::Doctor(subcommand) => {
+            cli::finish(cli::doctor::run(subcommand));
+        }
     }
 }
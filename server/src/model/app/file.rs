@@ -1,12 +1,22 @@
+use crate::api::{self, BaseUrl};
 use crate::db::{self, schema::*};
-use crate::model::User;
-use crate::util::{self, error::Res};
+use crate::model::{PasswordHash, Quota, User};
+use crate::util::{
+    self,
+    error::{Error as E, Res},
+    secret,
+};
 use chrono::prelude::*;
 use diesel::{self, prelude::*, SaveChangesDsl};
 
-#[derive(Identifiable, AsChangeset, Queryable, Associations, Serialize, PartialEq, Debug)]
+/// Ids per statement when changing many files at once; SQLite takes at
+/// most 999 parameters.
+const ID_CHUNK: usize = 500;
+
+#[derive(Identifiable, AsChangeset, Queryable, Associations, Serialize, PartialEq)]
 #[belongs_to(User)]
 #[table_name = "files"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct File {
     pub id: i32,
     pub user_id: i32,
@@ -16,6 +26,15 @@ pub struct File {
     pub delete_date: NaiveDateTime,
     pub downloads: i32,
     pub bytes: i64,
+    #[serde(skip_serializing)]
+    pub password_hash: Option<Vec<u8>>,
+    #[serde(skip_serializing)]
+    pub password_salt: Option<String>,
+    pub max_downloads: Option<i32>,
+    #[serde(skip_serializing)]
+    pub password_phc: Option<String>,
+    /// Sniffed from the data at upload, `None` if it wasn't recognized.
+    pub content_type: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -28,6 +47,41 @@ pub struct FileInsert {
     pub delete_date: NaiveDateTime,
     pub downloads: i32,
     pub bytes: i64,
+    pub password_hash: Option<Vec<u8>>,
+    pub password_salt: Option<String>,
+    pub max_downloads: Option<i32>,
+    pub password_phc: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// A share link password, hashed like account passwords.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FileLock {
+    pub hash: PasswordHash,
+}
+
+impl FileLock {
+    pub fn new(password: &str) -> Self {
+        FileLock {
+            hash: PasswordHash::new(password),
+        }
+    }
+
+    pub fn is_valid(&self, password: &str) -> bool {
+        self.hash.verify(password)
+    }
+
+    pub fn needs_rehash(&self) -> bool {
+        self.hash.needs_rehash()
+    }
+
+    /// The `(password_phc, password_hash, password_salt)` columns of a file.
+    pub fn columns(lock: Option<&FileLock>) -> (Option<String>, Option<Vec<u8>>, Option<String>) {
+        match lock {
+            Some(lock) => lock.hash.to_columns(),
+            None => (None, None, None),
+        }
+    }
 }
 
 impl File {
@@ -39,6 +93,42 @@ impl File {
         Ok(files::table.filter(files::key.eq(key)).first(&**c)?)
     }
 
+    pub fn expired(now: NaiveDateTime, c: &db::Connection) -> Res<Vec<Self>> {
+        Ok(files::table
+            .filter(files::delete_date.le(now))
+            .get_results(&**c)?)
+    }
+
+    /// Files matching all of the given conditions, oldest first.
+    pub fn list(
+        user_id: Option<i32>,
+        expired_at: Option<NaiveDateTime>,
+        min_bytes: Option<i64>,
+        c: &db::Connection,
+    ) -> Res<Vec<Self>> {
+        let mut query = files::table.into_boxed();
+        if let Some(user_id) = user_id {
+            query = query.filter(files::user_id.eq(user_id));
+        }
+        if let Some(now) = expired_at {
+            query = query.filter(files::delete_date.le(now));
+        }
+        if let Some(bytes) = min_bytes {
+            query = query.filter(files::bytes.gt(bytes));
+        }
+        Ok(query.order(files::upload_date.asc()).load(&**c)?)
+    }
+
+    /// Files that are expired or have used up their downloads.
+    pub fn reapable(now: NaiveDateTime, c: &db::Connection) -> Res<Vec<Self>> {
+        use diesel::{dsl::sql, sql_types::Bool};
+        Ok(files::table
+            .filter(files::delete_date.le(now).or(sql::<Bool>(
+                "max_downloads IS NOT NULL AND downloads >= max_downloads",
+            )))
+            .get_results(&**c)?)
+    }
+
     pub fn insert_one(values: &FileInsert, c: &db::Connection) -> Res<Self> {
         diesel::insert_into(files::table)
             .values(values)
@@ -53,15 +143,97 @@ impl File {
         Self::insert_one(values, c)
     }
 
+    /// Record a new file if it still fits its owner's quota. Check and
+    /// insert share an immediate transaction, so concurrent uploads are
+    /// counted against each other.
+    pub fn create_within_quota(values: &FileInsert, c: &db::Connection) -> Res<File> {
+        c.immediate_transaction::<_, E, _>(|| {
+            Quota::check_user(values.user_id, values.bytes, c)?;
+            Self::insert_one(values, c)
+        })
+    }
+
     pub fn delete(id: i32, c: &db::Connection) -> Res<usize> {
         Ok(diesel::delete(files::table.filter(files::id.eq(id))).execute(&**c)?)
     }
 
+    /// Delete the given files of a user, leaving any others alone.
+    pub fn delete_by_user(user_id: i32, ids: &[i32], c: &db::Connection) -> Res<usize> {
+        let mut deleted = 0;
+        for ids in ids.chunks(ID_CHUNK) {
+            deleted += diesel::delete(
+                files::table
+                    .filter(files::user_id.eq(user_id))
+                    .filter(files::id.eq_any(ids)),
+            )
+            .execute(&**c)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Hand the given files of a user to another, leaving any others alone.
+    pub fn reassign(user_id: i32, ids: &[i32], to_user_id: i32, c: &db::Connection) -> Res<usize> {
+        let mut moved = 0;
+        for ids in ids.chunks(ID_CHUNK) {
+            moved += diesel::update(
+                files::table
+                    .filter(files::user_id.eq(user_id))
+                    .filter(files::id.eq_any(ids)),
+            )
+            .set(files::user_id.eq(to_user_id))
+            .execute(&**c)?;
+        }
+        Ok(moved)
+    }
+
     pub fn update(&self, c: &db::Connection) -> Res<()> {
         self.save_changes::<File>(&**c)?;
         Ok(())
     }
 
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.delete_date <= now
+    }
+
+    /// Count a download unless the file has used up its downloads. A single
+    /// statement, so concurrent downloads can't all take the last one.
+    pub fn claim_download(id: i32, c: &db::Connection) -> Res<bool> {
+        use diesel::{dsl::sql, sql_types::Bool};
+        let claimed = diesel::update(files::table.filter(files::id.eq(id)).filter(sql::<Bool>(
+            "max_downloads IS NULL OR downloads < max_downloads",
+        )))
+        .set(files::downloads.eq(files::downloads + 1))
+        .execute(&**c)?;
+        Ok(claimed == 1)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        match self.max_downloads {
+            Some(max) => self.downloads >= max,
+            None => false,
+        }
+    }
+
+    pub fn lock(&self) -> Option<FileLock> {
+        PasswordHash::from_columns(
+            self.password_phc.clone(),
+            self.password_hash.clone(),
+            self.password_salt.clone(),
+        )
+        .map(|hash| FileLock { hash })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.password_phc.is_some() || self.password_hash.is_some()
+    }
+
+    pub fn set_lock(&mut self, lock: Option<FileLock>) {
+        let (phc, hash, salt) = FileLock::columns(lock.as_ref());
+        self.password_phc = phc;
+        self.password_hash = hash;
+        self.password_salt = salt;
+    }
+
     pub fn is_duplicate(key: &str, c: &db::Connection) -> Res<bool> {
         let count = files::table
             .select(diesel::dsl::count(files::key.eq(key)))
@@ -69,14 +241,20 @@ impl File {
         return Ok(count > 1);
     }
 
-    pub fn collection_url() -> String {
-        use crate::api;
-        api::collection_url(api::RES_FILE)
+    pub fn collection_url(base: &BaseUrl) -> String {
+        base.collection_url(api::RES_FILE)
+    }
+
+    pub fn url(&self, base: &BaseUrl) -> String {
+        base.resource_url(api::RES_FILE, self.id)
     }
 
-    pub fn url(&self) -> String {
-        use crate::api;
-        api::resource_url(api::RES_FILE, self.id)
+    pub fn download_url(&self, base: &BaseUrl) -> String {
+        base.download_url(&self.key)
+    }
+
+    pub fn view_url(&self, base: &BaseUrl) -> String {
+        format!("{}/view", base.download_url(&self.key))
     }
 }
 
@@ -86,3 +264,24 @@ impl Display for File {
         write!(f, "File[{}] @{} {}", self.id, self.key, self.val)
     }
 }
+
+/// Leaves out the share link password hash.
+impl core::fmt::Debug for File {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("File")
+            .field("id", &self.id)
+            .field("user_id", &self.user_id)
+            .field("key", &self.key)
+            .field("val", &self.val)
+            .field("upload_date", &self.upload_date)
+            .field("delete_date", &self.delete_date)
+            .field("downloads", &self.downloads)
+            .field("bytes", &self.bytes)
+            .field("password_hash", &secret::redacted(&self.password_hash))
+            .field("password_salt", &self.password_salt)
+            .field("max_downloads", &self.max_downloads)
+            .field("password_phc", &secret::redacted(&self.password_phc))
+            .field("content_type", &self.content_type)
+            .finish()
+    }
+}
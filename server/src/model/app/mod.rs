@@ -1,2 +1,2 @@
 pub mod file;
-pub use self::file::{File, FileInsert};
+pub use self::file::{File, FileInsert, FileLock};
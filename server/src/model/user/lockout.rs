@@ -0,0 +1,76 @@
+use crate::db::{self, schema::*};
+use crate::util::{self, date::UtcDateTime, error::Res};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+
+pub const LOCKOUT_IP: &'static str = "ip";
+pub const LOCKOUT_ACCOUNT: &'static str = "account";
+
+/// A client or account that was locked out after too many failed logins.
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name = "lockouts"]
+pub struct Lockout {
+    pub id: i32,
+    /// `ip` or `account`.
+    pub kind: String,
+    /// The address or the username that was tried.
+    pub subject: String,
+    pub failures: i32,
+    pub created_at: NaiveDateTime,
+    pub locked_until: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "lockouts"]
+pub struct LockoutInsert {
+    pub kind: String,
+    pub subject: String,
+    pub failures: i32,
+    pub created_at: NaiveDateTime,
+    pub locked_until: NaiveDateTime,
+}
+
+impl Lockout {
+    pub fn insert_one(values: &LockoutInsert, c: &db::Connection) -> Res<Self> {
+        diesel::insert_into(lockouts::table)
+            .values(values)
+            .execute(&**c)?;
+
+        Ok(lockouts::table
+            .filter(util::sql::with_rowid(util::sql::last_insert_rowid(c)))
+            .first(&**c)?)
+    }
+
+    pub fn record(
+        kind: &str,
+        subject: &str,
+        failures: u32,
+        duration: std::time::Duration,
+        c: &db::Connection,
+    ) -> Res<Self> {
+        let now = Utc::now();
+        let until = now + Duration::seconds(duration.as_secs() as i64);
+        warn!(
+            "{} {} locked out after {} failures",
+            kind, subject, failures
+        );
+        Self::insert_one(
+            &LockoutInsert {
+                kind: kind.into(),
+                subject: subject.into(),
+                failures: failures as i32,
+                created_at: UtcDateTime(now).into(),
+                locked_until: UtcDateTime(until).into(),
+            },
+            c,
+        )
+    }
+
+    /// The latest lockouts, newest first.
+    pub fn recent(limit: i64, c: &db::Connection) -> Res<Vec<Self>> {
+        Ok(lockouts::table
+            .order(lockouts::id.desc())
+            .limit(limit)
+            .load(&**c)?)
+    }
+}
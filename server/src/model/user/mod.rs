@@ -6,3 +6,15 @@ pub use self::password::*;
 
 pub mod session;
 pub use self::session::*;
+
+pub mod quota;
+pub use self::quota::*;
+
+pub mod removal;
+pub use self::removal::*;
+
+pub mod token;
+pub use self::token::*;
+
+pub mod lockout;
+pub use self::lockout::*;
@@ -1,23 +1,136 @@
+use crate::config;
 use crate::db::{self, schema::*};
 use crate::model::User;
-use crate::util::{self, error::Res, random::random_ascii};
+use crate::util::{
+    self,
+    error::Res,
+    secret::{self, Secret},
+};
+use argon2::{self, ThreadMode, Variant, Version};
 use argon2rs::argon2i_simple;
 use diesel::{self, prelude::*};
 
 const SALT_LEN: usize = 16;
-const PEANUTS: &'static str = "peanut-butter-jelly-time!";
+const HASH_LEN: u32 = 32;
+/// Pepper of the argon2i hashes written before PHC strings, only used to
+/// check those until they are replaced.
+const LEGACY_PEPPER: &'static str = "peanut-butter-jelly-time!";
 
-#[derive(Identifiable, Insertable, Queryable, Associations, Serialize, PartialEq, Debug)]
+#[derive(Identifiable, Insertable, Queryable, Associations, Serialize, PartialEq)]
 #[primary_key(user_id)]
 #[belongs_to(User)]
 #[table_name = "passwords"]
 pub struct Password {
     pub user_id: i32,
-    pub hash: Vec<u8>,
-    pub salt: String,
+    #[serde(skip_serializing)]
+    pub hash: Option<Vec<u8>>,
+    #[serde(skip_serializing)]
+    pub salt: Option<String>,
+    #[serde(skip_serializing)]
+    pub phc: Option<String>,
+}
+
+/// A stored password: an argon2id PHC string, or the argon2i hash and salt
+/// of older versions, which is replaced once the password is known again.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum PasswordHash {
+    Phc(String),
+    Legacy { hash: Vec<u8>, salt: String },
+}
+
+impl PasswordHash {
+    pub fn new(password: &str) -> Self {
+        let settings = &config::get().password;
+        let pepper = settings.pepper.as_ref().map(Secret::expose).unwrap_or("");
+        let argon = argon2::Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: settings.memory_kib,
+            time_cost: settings.iterations,
+            lanes: settings.parallelism,
+            thread_mode: ThreadMode::Sequential,
+            secret: pepper.as_bytes(),
+            ad: &[],
+            hash_length: HASH_LEN,
+        };
+        let salt = rand::random::<[u8; SALT_LEN]>();
+        PasswordHash::Phc(
+            argon2::hash_encoded(password.as_bytes(), &salt, &argon)
+                .expect("[password] argon2 parameters are checked at startup"),
+        )
+    }
+
+    /// Read the hash from its columns, legacy ones only if there is no PHC string.
+    pub fn from_columns(
+        phc: Option<String>,
+        hash: Option<Vec<u8>>,
+        salt: Option<String>,
+    ) -> Option<Self> {
+        match (phc, hash, salt) {
+            (Some(phc), _, _) => Some(PasswordHash::Phc(phc)),
+            (None, Some(hash), Some(salt)) => Some(PasswordHash::Legacy { hash, salt }),
+            _ => None,
+        }
+    }
+
+    /// Split into the `(phc, hash, salt)` columns.
+    pub fn to_columns(&self) -> (Option<String>, Option<Vec<u8>>, Option<String>) {
+        match self {
+            PasswordHash::Phc(phc) => (Some(phc.clone()), None, None),
+            PasswordHash::Legacy { hash, salt } => (None, Some(hash.clone()), Some(salt.clone())),
+        }
+    }
+
+    /// Compare in constant time.
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            PasswordHash::Phc(phc) => {
+                let settings = &config::get().password;
+                let pepper = settings.pepper.as_ref().map(Secret::expose).unwrap_or("");
+                argon2::verify_encoded_ext(phc, password.as_bytes(), pepper.as_bytes(), &[])
+                    .unwrap_or(false)
+            }
+            PasswordHash::Legacy { hash, salt } => {
+                let candidate = argon2i_simple(password, &(salt.to_owned() + LEGACY_PEPPER));
+                constant_time_eq(&candidate, hash)
+            }
+        }
+    }
+
+    /// Whether this hash is weaker than what is configured now.
+    pub fn needs_rehash(&self) -> bool {
+        match self {
+            PasswordHash::Phc(phc) => {
+                let settings = &config::get().password;
+                let expected = format!(
+                    "$argon2id$v=19$m={},t={},p={}$",
+                    settings.memory_kib, settings.iterations, settings.parallelism
+                );
+                !phc.starts_with(&expected)
+            }
+            PasswordHash::Legacy { .. } => true,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
 }
 
 impl Password {
+    pub fn new(user_id: i32, password: &PasswordHash) -> Self {
+        let (phc, hash, salt) = password.to_columns();
+        Password {
+            user_id: user_id,
+            hash: hash,
+            salt: salt,
+            phc: phc,
+        }
+    }
+
     pub fn insert_one(values: &Password, c: &db::Connection) -> Res<Self> {
         diesel::insert_into(passwords::table)
             .values(values)
@@ -27,15 +140,40 @@ impl Password {
             .first(&**c)?)
     }
 
-    pub fn salt() -> String {
-        random_ascii(SALT_LEN)
+    pub fn set(user_id: i32, password: &str, c: &db::Connection) -> Res<()> {
+        let (phc, hash, salt) = PasswordHash::new(password).to_columns();
+        diesel::update(passwords::table.filter(passwords::user_id.eq(user_id)))
+            .set((
+                passwords::phc.eq(phc),
+                passwords::hash.eq(hash),
+                passwords::salt.eq(salt),
+            ))
+            .execute(&**c)?;
+        Ok(())
     }
 
-    pub fn hash(password: &str, salt: &str) -> Vec<u8> {
-        argon2i_simple(password, &(salt.to_owned() + PEANUTS)).to_vec()
+    pub fn to_hash(&self) -> Option<PasswordHash> {
+        PasswordHash::from_columns(self.phc.clone(), self.hash.clone(), self.salt.clone())
     }
+}
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+impl Debug for Password {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Password")
+            .field("user_id", &self.user_id)
+            .field("hash", &secret::redacted(&self.hash))
+            .field("salt", &self.salt)
+            .field("phc", &secret::redacted(&self.phc))
+            .finish()
+    }
+}
 
-    pub fn is_valid(password: &str, hash: &Vec<u8>, salt: &str) -> bool {
-        Self::hash(password, salt) == *hash
+impl Debug for PasswordHash {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            PasswordHash::Phc(_) => write!(f, "Phc([redacted])"),
+            PasswordHash::Legacy { .. } => write!(f, "Legacy([redacted])"),
+        }
     }
 }
@@ -0,0 +1,133 @@
+use crate::db::{self, schema::*};
+use crate::model::User;
+use crate::util::error::Res;
+use diesel::{self, prelude::*};
+
+#[derive(
+    Identifiable, Insertable, AsChangeset, Queryable, Associations, Serialize, PartialEq, Debug,
+)]
+#[primary_key(user_id)]
+#[belongs_to(User)]
+#[table_name = "quotas"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct Quota {
+    pub user_id: i32,
+    pub max_bytes: Option<i64>,
+    pub max_files: Option<i32>,
+    pub max_file_bytes: Option<i64>,
+}
+
+/// What a user currently stores.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Usage {
+    pub files: i64,
+    pub bytes: i64,
+}
+
+/// What one user stores, by name.
+#[derive(QueryableByName, Serialize, PartialEq, Debug)]
+pub struct UserUsage {
+    #[sql_type = "diesel::sql_types::Text"]
+    pub name: String,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub files: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub bytes: i64,
+}
+
+#[derive(Debug, Fail)]
+pub enum QuotaError {
+    #[fail(display = "file too large: {} > {}", bytes, limit)]
+    FileTooLarge { bytes: i64, limit: i64 },
+    #[fail(display = "storage quota exceeded: {} + {} > {}", used, bytes, limit)]
+    BytesExceeded { used: i64, bytes: i64, limit: i64 },
+    #[fail(display = "file quota exceeded: {} >= {}", files, limit)]
+    FilesExceeded { files: i64, limit: i64 },
+}
+
+impl Quota {
+    pub fn unlimited(user_id: i32) -> Self {
+        Quota {
+            user_id: user_id,
+            max_bytes: None,
+            max_files: None,
+            max_file_bytes: None,
+        }
+    }
+
+    pub fn by_user(user_id: i32, c: &db::Connection) -> Res<Self> {
+        Ok(quotas::table
+            .filter(quotas::user_id.eq(user_id))
+            .first(&**c)
+            .optional()?
+            .unwrap_or_else(|| Self::unlimited(user_id)))
+    }
+
+    /// Store this quota, replacing any previous one.
+    pub fn save(&self, c: &db::Connection) -> Res<()> {
+        diesel::replace_into(quotas::table)
+            .values(self)
+            .execute(&**c)?;
+        Ok(())
+    }
+
+    /// Check whether another file of `bytes` fits.
+    pub fn check(&self, bytes: i64, usage: &Usage) -> Result<(), QuotaError> {
+        if let Some(limit) = self.max_file_bytes {
+            if bytes > limit {
+                return Err(QuotaError::FileTooLarge { bytes, limit });
+            }
+        }
+        if let Some(limit) = self.max_files {
+            if usage.files >= limit as i64 {
+                return Err(QuotaError::FilesExceeded {
+                    files: usage.files,
+                    limit: limit as i64,
+                });
+            }
+        }
+        if let Some(limit) = self.max_bytes {
+            if usage.bytes + bytes > limit {
+                return Err(QuotaError::BytesExceeded {
+                    used: usage.bytes,
+                    bytes,
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a user's quota against another file of `bytes`.
+    pub fn check_user(user_id: i32, bytes: i64, c: &db::Connection) -> Res<()> {
+        let quota = Self::by_user(user_id, c)?;
+        let usage = Usage::of(user_id, c)?;
+        if let Err(error) = quota.check(bytes, &usage) {
+            info!("user[{}] rejected: {}", user_id, error);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+impl Usage {
+    pub fn of(user_id: i32, c: &db::Connection) -> Res<Self> {
+        use diesel::{dsl::sql, sql_types::BigInt};
+        let (files, bytes) = files::table
+            .filter(files::user_id.eq(user_id))
+            .select(sql::<(BigInt, BigInt)>("COUNT(*), COALESCE(SUM(bytes), 0)"))
+            .first::<(i64, i64)>(&**c)?;
+        Ok(Usage { files, bytes })
+    }
+
+    /// Files and bytes of every user, including those without files.
+    pub fn all(c: &db::Connection) -> Res<Vec<UserUsage>> {
+        Ok(diesel::sql_query(
+            "SELECT users.name AS name, COUNT(files.id) AS files, \
+             COALESCE(SUM(files.bytes), 0) AS bytes \
+             FROM users LEFT JOIN files ON files.user_id = users.id \
+             GROUP BY users.id ORDER BY users.name",
+        )
+        .load(&**c)?)
+    }
+}
@@ -0,0 +1,142 @@
+use crate::api::app::files::user_spool;
+use crate::db;
+use crate::model::{File, Session, User};
+use crate::storage::{Storage, StorageError};
+use crate::util::error::{Error as E, Res, ServerError};
+use crate::util::thumbnail;
+use std::fs;
+use std::path::PathBuf;
+
+/// What happens to the files of a deleted user.
+#[derive(Debug)]
+pub enum Disposal {
+    Remove,
+    Reassign(User),
+}
+
+/// Everything that goes away with a user, gathered before anything is touched.
+#[derive(Debug)]
+pub struct Removal {
+    pub user: User,
+    pub disposal: Disposal,
+    pub files: Vec<File>,
+    pub sessions: usize,
+    /// Partial uploads and leftovers in the user's spool directory.
+    pub spool: Vec<PathBuf>,
+}
+
+fn is_missing(error: &E) -> bool {
+    match error {
+        E::ServerError(ServerError::StorageError {
+            error: StorageError::NotFound { .. },
+        }) => true,
+        _ => false,
+    }
+}
+
+impl Removal {
+    pub fn plan(user: User, disposal: Disposal, c: &db::Connection) -> Res<Self> {
+        let files = File::list(Some(user.id), None, None, c)?;
+        let sessions = Session::by_user(user.id, c)?.len();
+        let spool = match fs::read_dir(user_spool(user.id)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Ok(Removal {
+            user: user,
+            disposal: disposal,
+            files: files,
+            sessions: sessions,
+            spool: spool,
+        })
+    }
+
+    pub fn bytes(&self) -> i64 {
+        self.files.iter().map(|file| file.bytes).sum()
+    }
+
+    /// Files uploaded since the plan was made stop the removal, their blobs
+    /// would otherwise be left without a record.
+    fn check_unplanned(&self, c: &db::Connection) -> Res<()> {
+        let left = File::list(Some(self.user.id), None, None, c)?.len();
+        match left {
+            0 => Ok(()),
+            left => Err(ServerError::SqlInvariantError {
+                message: format!(
+                    "{} has {} files that weren't planned for, try again",
+                    self.user, left
+                ),
+            }
+            .into()),
+        }
+    }
+
+    fn ids(&self) -> Vec<i32> {
+        self.files.iter().map(|file| file.id).collect()
+    }
+
+    /// Blobs are handed over before and removed after the database
+    /// transaction, so a failure leaves at worst orphaned blobs behind,
+    /// never records without data. Only the planned files are touched.
+    pub fn execute(&self, storage: &dyn Storage, c: &db::Connection) -> Res<()> {
+        match self.disposal {
+            Disposal::Remove => {
+                c.transaction::<_, E, _>(|| {
+                    File::delete_by_user(self.user.id, &self.ids(), c)?;
+                    self.check_unplanned(c)?;
+                    Session::revoke_all(self.user.id, c)?;
+                    User::delete(self.user.id, c)
+                })?;
+                for file in &self.files {
+                    if let Err(error) = storage.delete(file.user_id, &file.key) {
+                        warn!("failed to remove {}: {:?}", file, error);
+                    }
+                    thumbnail::remove(&file.key);
+                }
+            }
+            Disposal::Reassign(ref heir) => {
+                let mut moved = Vec::new();
+                for file in &self.files {
+                    match storage.relocate(self.user.id, &file.key, heir.id) {
+                        Ok(()) => moved.push(file),
+                        Err(ref error) if is_missing(error) => {
+                            warn!("{} already missing in storage", file);
+                        }
+                        Err(error) => {
+                            self.restore(&moved, heir, storage);
+                            return Err(error);
+                        }
+                    }
+                }
+                let result = c.transaction::<_, E, _>(|| {
+                    File::reassign(self.user.id, &self.ids(), heir.id, c)?;
+                    self.check_unplanned(c)?;
+                    Session::revoke_all(self.user.id, c)?;
+                    User::delete(self.user.id, c)
+                });
+                if let Err(error) = result {
+                    self.restore(&moved, heir, storage);
+                    return Err(error);
+                }
+            }
+        }
+        if let Err(error) = fs::remove_dir_all(user_spool(self.user.id)) {
+            if !self.spool.is_empty() {
+                warn!("failed to remove spool of {}: {:?}", self.user, error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Give relocated blobs back after a failed reassignment.
+    fn restore(&self, moved: &[&File], heir: &User, storage: &dyn Storage) {
+        for file in moved {
+            if let Err(error) = storage.relocate(heir.id, &file.key, self.user.id) {
+                error!("failed to restore {}: {:?}", file, error);
+            }
+        }
+    }
+}
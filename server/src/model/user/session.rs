@@ -1,38 +1,62 @@
+use crate::api::Device;
+use crate::config;
 use crate::db::{self, schema::*};
 use crate::model::User;
 use crate::util::{
     self,
+    date::UtcDateTime,
     error::{Error as ApiError, Res},
     random::random_ascii,
+    secret::Secret,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, prelude::*, SaveChangesDsl};
+use rocket::http::{Cookie, Cookies, Status};
+use rocket::{
+    request::{self, FromRequest, Request},
+    Outcome,
 };
-use diesel::{self, prelude::*};
-use rocket::http::{Cookie, Cookies};
 
 const SESSION_TOKEN_KEY: &'static str = "__session_token";
 const SESSION_TOKEN_LEN: usize = 32;
+/// Seconds between writes of `last_seen`, so not every request hits the disk.
+const SESSION_TOUCH_INTERVAL: i64 = 60;
 
-#[derive(Identifiable, Insertable, Queryable, Associations, Serialize, PartialEq, Debug)]
+#[derive(
+    Identifiable, Insertable, AsChangeset, Queryable, Associations, Serialize, PartialEq, Debug,
+)]
 #[belongs_to(User)]
 #[table_name = "sessions"]
 pub struct Session {
     pub id: i32,
     pub user_id: i32,
-    pub token: String,
+    #[serde(skip_serializing)]
+    pub token: Secret,
+    pub created_at: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
 }
 
 #[derive(Debug, Fail)]
 pub enum SessionError {
     #[fail(display = "session cookie not found")]
     CookieNotFound {},
-    #[fail(display = "session record not found: {}", token)]
-    RecordNotFound { token: String },
+    #[fail(display = "session record not found")]
+    RecordNotFound {},
+    #[fail(display = "no such session: {}", id)]
+    NotFound { id: i32 },
 }
 
 #[derive(Insertable)]
 #[table_name = "sessions"]
 pub struct SessionInsert {
     pub user_id: i32,
-    pub token: String,
+    pub token: Secret,
+    pub created_at: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
 }
 
 impl Session {
@@ -52,9 +76,74 @@ impl Session {
         Ok(result)
     }
 
+    pub fn by_user(user_id: i32, c: &db::Connection) -> Res<Vec<Self>> {
+        Ok(sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .order(sessions::id.asc())
+            .load(&**c)?)
+    }
+
+    pub fn revoke(id: i32, user_id: i32, c: &db::Connection) -> Res<usize> {
+        let result = diesel::delete(
+            sessions::table
+                .filter(sessions::id.eq(id))
+                .filter(sessions::user_id.eq(user_id)),
+        )
+        .execute(&**c)?;
+        Ok(result)
+    }
+
+    /// Revoke every session of the user but `keep`.
+    pub fn revoke_others(user_id: i32, keep: i32, c: &db::Connection) -> Res<usize> {
+        let result = diesel::delete(
+            sessions::table
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::id.ne(keep)),
+        )
+        .execute(&**c)?;
+        Ok(result)
+    }
+
+    /// Remove sessions past their absolute or idle timeout.
+    pub fn delete_expired(now: NaiveDateTime, c: &db::Connection) -> Res<usize> {
+        let config = config::get();
+        let created = now - Duration::seconds(config.session_lifetime as i64);
+        let seen = now - Duration::seconds(config.session_idle as i64);
+        let result = diesel::delete(
+            sessions::table.filter(
+                sessions::created_at
+                    .le(created)
+                    .or(sessions::last_seen.le(seen)),
+            ),
+        )
+        .execute(&**c)?;
+        Ok(result)
+    }
+
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        let config = config::get();
+        self.created_at + Duration::seconds(config.session_lifetime as i64) <= now
+            || self.last_seen + Duration::seconds(config.session_idle as i64) <= now
+    }
+
+    /// Record activity, at most every `SESSION_TOUCH_INTERVAL` seconds.
+    pub fn touch(&mut self, now: NaiveDateTime, c: &db::Connection) -> Res<()> {
+        if now - self.last_seen >= Duration::seconds(SESSION_TOUCH_INTERVAL) {
+            self.last_seen = now;
+            self.save_changes::<Session>(&**c)?;
+        }
+        Ok(())
+    }
+
+    pub fn revoke_all(user_id: i32, c: &db::Connection) -> Res<usize> {
+        let result =
+            diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(&**c)?;
+        Ok(result)
+    }
+
     pub fn set_cookie(&self, cookies: &mut Cookies) {
         use base64::encode;
-        let cookie = Cookie::build(SESSION_TOKEN_KEY, encode(&self.token))
+        let cookie = Cookie::build(SESSION_TOKEN_KEY, encode(self.token.expose()))
             .path("/")
             .http_only(true)
             .finish();
@@ -77,20 +166,38 @@ impl Session {
         match Self::get_cookie(cookies) {
             Ok(token) => match token {
                 Some(value) => {
-                    println!("[session {}] cookie found", value);
+                    debug!("cookie found");
                     let result = sessions::table
                         .filter(sessions::token.eq(&value))
-                        .first::<Session>(&**c)?;
-                    Ok(Some(result))
+                        .first::<Session>(&**c)
+                        .optional()?;
+                    let now = UtcDateTime(Utc::now()).into();
+                    match result {
+                        Some(ref session) if session.is_expired(now) => {
+                            info!("session[{}] expired", session.id);
+                            Self::delete(session.token.expose(), c)?;
+                            Self::del_cookie(cookies);
+                            Ok(None)
+                        }
+                        Some(mut session) => {
+                            session.touch(now, c)?;
+                            Ok(Some(session))
+                        }
+                        /* Revoked elsewhere. */
+                        None => {
+                            Self::del_cookie(cookies);
+                            Ok(None)
+                        }
+                    }
                 }
                 None => {
-                    println!("[session] no cookie found");
+                    debug!("no cookie found");
                     Ok(None)
                 }
             },
             /* Delete the cookie if there was an error. */
             Err(error) => {
-                println!("[session] cookie error: deleting session cookie");
+                warn!("cookie error: deleting session cookie");
                 Self::del_cookie(cookies);
                 Err(error)
             }
@@ -99,18 +206,24 @@ impl Session {
 
     pub fn is_duplicate(token: &str, c: &db::Connection) -> Res<bool> {
         let count = sessions::table
-            .select(diesel::dsl::count(sessions::token.eq(token)))
-            .execute(&**c)?;
-        return Ok(count > 1);
+            .filter(sessions::token.eq(token))
+            .count()
+            .get_result::<i64>(&**c)?;
+        return Ok(count > 0);
     }
 
     pub fn token() -> String {
         random_ascii(SESSION_TOKEN_LEN)
     }
 
-    pub fn create(user_id: i32, c: &db::Connection, cookies: &mut Cookies) -> Res<Session> {
+    pub fn create(
+        user_id: i32,
+        device: &Device,
+        c: &db::Connection,
+        cookies: &mut Cookies,
+    ) -> Res<Session> {
         c.transaction::<_, ApiError, _>(|| {
-            println!("[uid {}] creating session", user_id);
+            debug!("user[{}] creating session", user_id);
 
             /* generate a token. */
             let mut token = Self::token();
@@ -119,49 +232,35 @@ impl Session {
             }
 
             /* store new session. */
+            let now = UtcDateTime(Utc::now()).into();
             let session = Session::insert_one(
                 &SessionInsert {
                     user_id: user_id,
-                    token: token,
+                    token: token.into(),
+                    created_at: now,
+                    last_seen: now,
+                    user_agent: device.user_agent.clone(),
+                    ip: device.ip.clone(),
                 },
                 c,
             )?;
 
-            println!("[uid {}] started session [{}]", user_id, session.token);
+            info!("user[{}] started session {}", user_id, session.id);
             session.set_cookie(cookies);
             Ok(session)
         })
     }
 
-    pub fn login(user_id: i32, c: &db::Connection, cookies: &mut Cookies) -> Res<Session> {
-        use diesel::result::Error::NotFound;
-
-        c.transaction::<_, ApiError, _>(|| {
-            /* Find the stored session. */
-            let query = sessions::table
-                .filter(sessions::user_id.eq(user_id))
-                .first::<Session>(&**c);
-
-            match query {
-                Ok(session) => {
-                    /* A session was found, tell the client to store a cookie. */
-                    println!("[uid {}] setting session cookie", user_id);
-                    session.set_cookie(cookies);
-                    Ok(session)
-                }
-                Err(error) => match error {
-                    /* If no session was found we need to create a new one. */
-                    NotFound => {
-                        println!("[uid {}] creating new session", user_id);
-                        Self::create(user_id, c, cookies)
-                    }
-                    _ => {
-                        println!("[uid {}] error during login: {:?}", user_id, error);
-                        Err(error.into())
-                    }
-                },
-            }
-        })
+    /// Every login starts its own session, so devices can be told apart
+    /// and logged out one by one.
+    pub fn login(
+        user_id: i32,
+        device: &Device,
+        c: &db::Connection,
+        cookies: &mut Cookies,
+    ) -> Res<Session> {
+        debug!("user[{}] creating new session", user_id);
+        Self::create(user_id, device, c, cookies)
     }
 
     pub fn logout(c: &db::Connection, cookies: &mut Cookies) -> Res<usize> {
@@ -172,32 +271,26 @@ impl Session {
                 let deleted = Self::delete(&token, &c)?;
                 match deleted {
                     1 => {
-                        println!("[session {}] logout successfull", token);
+                        info!("logout successfull");
                         Ok(1)
                     }
                     0 => {
-                        println!(
-                            "[session {}] logout unsuccessfull: session not found",
-                            token
-                        );
-                        Err(SessionError::RecordNotFound {
-                            token: token.into(),
-                        }
-                        .into())
+                        info!("logout unsuccessfull: session not found");
+                        Err(SessionError::RecordNotFound {}.into())
                     }
                     _ => {
                         let message = String::from("DELETE: affected too many rows");
-                        println!("[session {}] critical error: {}", token, message);
+                        error!("critical error: {}", message);
                         Err(ServerError::SqlInvariantError { message }.into())
                     }
                 }
             }
             Ok(None) => {
-                println!("[session] session cookie not found");
+                info!("session cookie not found");
                 Err(SessionError::CookieNotFound {}.into())
             }
             Err(error) => {
-                println!("[session] error while reading cookie");
+                warn!("error while reading cookie");
                 Err(error)
             }
         };
@@ -206,3 +299,52 @@ impl Session {
         result
     }
 }
+
+// {{{ Session
+
+/// The session the request was made with.
+impl<'a, 'r> FromRequest<'a, 'r> for Session {
+    type Error = ApiError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        use crate::util::error::ServerError;
+
+        let connection = match request.guard::<db::Connection>() {
+            Outcome::Success(value) => value,
+            _ => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    ServerError::DataGuardError {
+                        name: String::from("Connection"),
+                    }
+                    .into(),
+                ))
+            }
+        };
+
+        let mut cookies = match request.guard::<Cookies>() {
+            Outcome::Success(value) => value,
+            _ => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    ServerError::DataGuardError {
+                        name: String::from("Cookies"),
+                    }
+                    .into(),
+                ))
+            }
+        };
+
+        match Session::from_cookie(&mut cookies, &connection) {
+            Ok(Some(session)) => Outcome::Success(session),
+            Ok(None) => Outcome::Forward(()),
+            Err(error) => {
+                warn!("error: {}", error);
+                Session::del_cookie(&mut cookies);
+                Outcome::Forward(())
+            }
+        }
+    }
+}
+
+// }}}
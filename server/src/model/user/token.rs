@@ -0,0 +1,166 @@
+use crate::db::{self, schema::*};
+use crate::model::User;
+use crate::util::{self, date::UtcDateTime, error::Res, random::random_ascii};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+use sha2::{Digest, Sha256};
+
+/// Prefix of every token, so they are recognizable in config files and logs.
+pub const TOKEN_PREFIX: &'static str = "flink_";
+const TOKEN_LEN: usize = 40;
+const TOKEN_NAME_LEN: usize = 64;
+
+/// A personal access token. Only its hash is stored, the token itself is
+/// shown once when it is created.
+#[derive(Identifiable, Queryable, Associations, Serialize, PartialEq)]
+#[belongs_to(User)]
+#[table_name = "tokens"]
+pub struct Token {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub hash: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "tokens"]
+pub struct TokenInsert {
+    pub user_id: i32,
+    pub name: String,
+    pub hash: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Fail)]
+pub enum TokenError {
+    #[fail(display = "no such token: {}", id)]
+    NotFound { id: i32 },
+    #[fail(display = "invalid token name")]
+    InvalidName {},
+    #[fail(display = "invalid token expiry: {}", string)]
+    InvalidExpiry { string: String },
+}
+
+impl Token {
+    pub fn insert_one(values: &TokenInsert, c: &db::Connection) -> Res<Self> {
+        diesel::insert_into(tokens::table)
+            .values(values)
+            .execute(&**c)?;
+
+        Ok(tokens::table
+            .filter(util::sql::with_rowid(util::sql::last_insert_rowid(c)))
+            .first(&**c)?)
+    }
+
+    /// Tokens are long and random, a plain digest is enough to keep them
+    /// useless when the database leaks.
+    pub fn hash(secret: &str) -> Vec<u8> {
+        Sha256::digest(secret.as_bytes()).to_vec()
+    }
+
+    /// Create a token, returns the record and the secret to hand out.
+    pub fn create(
+        user_id: i32,
+        name: &str,
+        expires_at: Option<NaiveDateTime>,
+        c: &db::Connection,
+    ) -> Res<(Token, String)> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > TOKEN_NAME_LEN {
+            return Err(TokenError::InvalidName {}.into());
+        }
+        let secret = format!("{}{}", TOKEN_PREFIX, random_ascii(TOKEN_LEN));
+        let token = Self::insert_one(
+            &TokenInsert {
+                user_id: user_id,
+                name: name.into(),
+                hash: Self::hash(&secret),
+                created_at: UtcDateTime(Utc::now()).into(),
+                expires_at: expires_at,
+            },
+            c,
+        )?;
+        Ok((token, secret))
+    }
+
+    pub fn by_user(user_id: i32, c: &db::Connection) -> Res<Vec<Self>> {
+        Ok(tokens::table
+            .filter(tokens::user_id.eq(user_id))
+            .order(tokens::id.asc())
+            .load(&**c)?)
+    }
+
+    /// The secret of an `Authorization: Bearer ...` header value.
+    pub fn bearer(header: &str) -> Option<&str> {
+        let mut parts = header.trim().splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some(scheme), Some(secret)) if scheme.eq_ignore_ascii_case("bearer") => {
+                Some(secret.trim())
+            }
+            _ => None,
+        }
+    }
+
+    /// The unexpired token matching `secret`, marking it as used.
+    pub fn authenticate(secret: &str, c: &db::Connection) -> Res<Option<Self>> {
+        if !secret.starts_with(TOKEN_PREFIX) {
+            return Ok(None);
+        }
+        let now: NaiveDateTime = UtcDateTime(Utc::now()).into();
+        let token = tokens::table
+            .filter(tokens::hash.eq(Self::hash(secret)))
+            .first::<Token>(&**c)
+            .optional()?;
+        match token {
+            Some(ref token) if token.is_expired(now) => Ok(None),
+            Some(mut token) => {
+                diesel::update(tokens::table.filter(tokens::id.eq(token.id)))
+                    .set(tokens::last_used.eq(now))
+                    .execute(&**c)?;
+                token.last_used = Some(now);
+                Ok(Some(token))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn revoke(id: i32, user_id: i32, c: &db::Connection) -> Res<()> {
+        let result = diesel::delete(
+            tokens::table
+                .filter(tokens::id.eq(id))
+                .filter(tokens::user_id.eq(user_id)),
+        )
+        .execute(&**c)?;
+        match result {
+            0 => Err(TokenError::NotFound { id }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+}
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+impl Debug for Token {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Token")
+            .field("id", &self.id)
+            .field("user_id", &self.user_id)
+            .field("name", &self.name)
+            .field("hash", &"[redacted]")
+            .field("created_at", &self.created_at)
+            .field("expires_at", &self.expires_at)
+            .field("last_used", &self.last_used)
+            .finish()
+    }
+}
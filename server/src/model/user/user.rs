@@ -1,5 +1,6 @@
+use crate::api::{self, BaseUrl, Device};
 use crate::db::{self, schema::*};
-use crate::model::{Password, Session};
+use crate::model::{Password, PasswordHash, Session, Token};
 use crate::util::{
     self,
     error::{Error as E, Res},
@@ -29,22 +30,17 @@ pub struct UserInsert {
 pub enum RegistrationError {
     #[fail(display = "duplicate username: '{}'", username)]
     DuplicateUsername { username: String },
-    #[fail(
-        display = "password mismatch: '{}' != '{}'",
-        password_one, password_two
-    )]
-    PasswordMismatch {
-        password_one: String,
-        password_two: String,
-    },
+    #[fail(display = "passwords do not match")]
+    PasswordMismatch {},
 }
 
+/// Unknown usernames and wrong passwords look the same to the client.
 #[derive(Debug, Fail)]
 pub enum AuthenticationError {
-    #[fail(display = "invalid username: '{}' -> '{}'", username, password)]
-    InvalidUsername { username: String, password: String },
-    #[fail(display = "invalid password: '{}' -> '{}'", username, password)]
-    InvalidPassword { username: String, password: String },
+    #[fail(display = "invalid username or password: '{}'", username)]
+    InvalidCredentials { username: String },
+    #[fail(display = "too many failed logins, retry in {}s", seconds)]
+    Throttled { seconds: u64 },
 }
 
 impl User {
@@ -58,14 +54,12 @@ impl User {
             .first(&**c)?)
     }
 
-    pub fn collection_url() -> String {
-        use crate::api;
-        api::collection_url(api::RES_USER)
+    pub fn collection_url(base: &BaseUrl) -> String {
+        base.collection_url(api::RES_USER)
     }
 
-    pub fn url(&self) -> String {
-        use crate::api;
-        api::resource_url(api::RES_USER, self.id)
+    pub fn url(&self, base: &BaseUrl) -> String {
+        base.resource_url(api::RES_USER, self.id)
     }
 
     pub fn by_id(id: i32, c: &db::Connection) -> Res<Self> {
@@ -78,21 +72,38 @@ impl User {
         Ok(user)
     }
 
+    pub fn all(c: &db::Connection) -> Res<Vec<Self>> {
+        Ok(users::table.order(users::name.asc()).load(&**c)?)
+    }
+
+    pub fn from_token(secret: &str, c: &db::Connection) -> Res<Option<Self>> {
+        match Token::authenticate(secret, c)? {
+            Some(token) => {
+                debug!("user[{}] token {} used", token.user_id, token.id);
+                Ok(Some(Self::by_id(token.user_id, c)?))
+            }
+            None => {
+                debug!("token not found");
+                Ok(None)
+            }
+        }
+    }
+
     pub fn from_cookie(cookies: &mut Cookies, c: &db::Connection) -> Res<Option<Self>> {
         match Session::from_cookie(cookies, c) {
             Ok(result) => match result {
                 Some(s) => {
-                    println!("[user {}] session found [{}]", s.user_id, s.token);
+                    debug!("user[{}] session {} found", s.user_id, s.id);
                     Ok(Some(Self::by_id(s.user_id, c)?))
                 }
                 None => {
-                    println!("[user] session not found");
+                    debug!("session not found");
                     Ok(None)
                 }
             },
             Err(error) => {
                 // TODO: handle this correctly..
-                println!("[user] session error: {:?}", error);
+                warn!("session error: {}", error);
                 Session::del_cookie(cookies);
                 Ok(None)
             }
@@ -107,7 +118,7 @@ impl User {
         c.transaction::<_, E, _>(|| {
             // Check for duplicate names.
             if Self::is_duplicate(&values.name, c)? {
-                println!("[user {}]: duplicate username", values.name);
+                info!("user[{}] duplicate username", values.name);
                 return Err(RegistrationError::DuplicateUsername {
                     username: values.name.clone(),
                 }
@@ -115,16 +126,8 @@ impl User {
             }
             // Create the user and password records.
             let user = Self::insert_one(values, c)?;
-            let salt = Password::salt();
-            let hash = Password::hash(password, &salt);
-            let pass = Password::insert_one(
-                &Password {
-                    user_id: user.id,
-                    hash: hash,
-                    salt: salt,
-                },
-                c,
-            )?;
+            let pass =
+                Password::insert_one(&Password::new(user.id, &PasswordHash::new(password)), c)?;
             Ok((user, pass))
         })
     }
@@ -142,30 +145,41 @@ impl User {
         Ok(())
     }
 
+    pub fn rename(&mut self, name: &str, c: &db::Connection) -> Res<()> {
+        c.transaction::<_, E, _>(|| {
+            if Self::is_duplicate(name, c)? {
+                return Err(RegistrationError::DuplicateUsername {
+                    username: name.into(),
+                }
+                .into());
+            }
+            self.name = name.into();
+            Self::update(self, c)
+        })
+    }
+
     pub fn is_duplicate(name: &str, c: &db::Connection) -> QueryResult<bool> {
         let count = users::table
-            .select(diesel::dsl::count(users::name.eq(name)))
-            .execute(&**c)?;
-        Ok(count > 1)
+            .filter(users::name.eq(name))
+            .count()
+            .get_result::<i64>(&**c)?;
+        Ok(count > 0)
     }
 
     pub fn register(
         name: &str,
         password_one: &str,
         password_two: &str,
+        device: &Device,
         c: &db::Connection,
         cookies: &mut Cookies,
     ) -> Res<(User, Password, Session)> {
         if password_one != password_two {
-            println!("[register {}]: password mismatch", name);
-            return Err(RegistrationError::PasswordMismatch {
-                password_one: password_one.into(),
-                password_two: password_two.into(),
-            }
-            .into());
+            info!("register[{}] password mismatch", name);
+            return Err(RegistrationError::PasswordMismatch {}.into());
         }
         c.transaction::<_, E, _>(|| {
-            println!("[register {}]: creating user", name);
+            info!("register[{}] creating user", name);
             let (user, pass) = Self::create(
                 &UserInsert {
                     name: name.to_string(),
@@ -173,8 +187,8 @@ impl User {
                 password_one,
                 c,
             )?;
-            println!("[register {}]: creating session", name);
-            let sess = Session::create(user.id, c, cookies)?;
+            debug!("register[{}] creating session", name);
+            let sess = Session::create(user.id, device, c, cookies)?;
             Ok((user, pass, sess))
         })
     }
@@ -182,45 +196,50 @@ impl User {
     pub fn login(
         username: &str,
         password: &str,
+        device: &Device,
         c: &db::Connection,
         cookies: &mut Cookies,
     ) -> Res<Session> {
         use diesel::result::Error::NotFound;
-        type Login = (i32, Vec<u8>, String);
 
         c.transaction::<_, E, _>(|| {
             let login = passwords::table
                 .inner_join(users::table)
                 .filter(users::name.eq(username))
-                .select((users::id, passwords::hash, passwords::salt))
-                .first::<Login>(&**c);
+                .select(passwords::all_columns)
+                .first::<Password>(&**c);
 
             match login {
-                Ok((user, hash, salt)) => match Password::is_valid(password, &hash, &salt) {
-                    true => {
-                        println!("[login {}] password valid", username);
-                        Ok(Session::login(user, c, cookies)?)
+                Ok(stored) => match stored.to_hash() {
+                    Some(ref hash) if hash.verify(password) => {
+                        info!("login[{}] password valid", username);
+                        /* The password is known right now, store it the current way. */
+                        if hash.needs_rehash() {
+                            info!("login[{}] upgrading password hash", username);
+                            Password::set(stored.user_id, password, c)?;
+                        }
+                        Ok(Session::login(stored.user_id, device, c, cookies)?)
                     }
-                    false => {
-                        println!("[login {}] password invalid", username);
-                        Err(AuthenticationError::InvalidPassword {
+                    _ => {
+                        info!("login[{}] password invalid", username);
+                        Err(AuthenticationError::InvalidCredentials {
                             username: username.into(),
-                            password: password.into(),
                         }
                         .into())
                     }
                 },
                 Err(error) => match error {
                     NotFound => {
-                        println!("[login {}] username invalid", username);
-                        Err(AuthenticationError::InvalidUsername {
+                        info!("login[{}] username invalid", username);
+                        /* Take as long as checking a password would. */
+                        PasswordHash::new(password);
+                        Err(AuthenticationError::InvalidCredentials {
                             username: username.into(),
-                            password: password.into(),
                         }
                         .into())
                     }
                     _ => {
-                        println!("[login {}] password error: {:?}", username, error);
+                        error!("login[{}] password error: {}", username, error);
                         Err(error.into())
                     }
                 },
@@ -250,30 +269,38 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
             }
         };
 
-        let mut cookies = match request.guard::<Cookies>() {
-            Outcome::Success(value) => value,
-            _ => {
-                return Outcome::Failure((
-                    Status::InternalServerError,
-                    ServerError::DataGuardError {
-                        name: String::from("Cookies"),
+        /* Scripts authenticate with a personal token instead of a cookie. */
+        let authorization = request.headers().get_one("Authorization");
+        let result = match authorization.and_then(Token::bearer) {
+            Some(secret) => User::from_token(secret, &connection),
+            None => {
+                let mut cookies = match request.guard::<Cookies>() {
+                    Outcome::Success(value) => value,
+                    _ => {
+                        return Outcome::Failure((
+                            Status::InternalServerError,
+                            ServerError::DataGuardError {
+                                name: String::from("Cookies"),
+                            }
+                            .into(),
+                        ))
                     }
-                    .into(),
-                ))
+                };
+                User::from_cookie(&mut cookies, &connection)
             }
         };
 
-        match User::from_cookie(&mut cookies, &connection) {
+        match result {
             Ok(Some(u)) => {
-                println!("[user {}] authenticated", u.id);
+                debug!("user[{}] authenticated", u.id);
                 Outcome::Success(u)
             }
             Ok(None) => {
-                println!("[user] not authenticated");
+                debug!("not authenticated");
                 Outcome::Forward(())
             }
             Err(error) => {
-                println!("[user] authentication error: {:?}", error);
+                error!("authentication error: {}", error);
                 Outcome::Failure((Status::InternalServerError, error))
             }
         }
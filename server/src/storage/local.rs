@@ -0,0 +1,138 @@
+use crate::api::app::files::{store_dir, user_store, user_store_file};
+use crate::storage::{Stat, Storage, StorageError};
+use crate::util::error::Res;
+
+use chrono::{DateTime, Utc};
+use std::fs::{self, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Blobs stored as `<dir>/store/<user_id>/<key>`.
+pub struct LocalStorage;
+
+impl LocalStorage {
+    fn to_stat(user_id: i32, key: &str, metadata: &Metadata) -> Stat {
+        Stat {
+            user_id: user_id,
+            key: key.into(),
+            bytes: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()
+                .map(|time| DateTime::<Utc>::from(time).naive_utc()),
+        }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, user_id: i32, key: &str, source: &Path) -> Res<u64> {
+        if !user_store(user_id).is_dir() {
+            if let Err(e) = fs::create_dir_all(user_store(user_id)) {
+                error!("failed to create user store directory: {}", e);
+                return Err(e.into());
+            }
+        }
+        let target = user_store_file(user_id, key);
+        if let Err(e) = fs::rename(source, &target) {
+            error!("failed to move file from spool location: {}", e);
+            return Err(e.into());
+        }
+        Ok(fs::metadata(&target)?.len())
+    }
+
+    fn get(&self, user_id: i32, key: &str) -> Res<Box<dyn Read + Send>> {
+        match fs::File::open(user_store_file(user_id, key)) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                Err(StorageError::NotFound {
+                    user_id: user_id,
+                    key: key.into(),
+                }
+                .into())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn get_range(
+        &self,
+        user_id: i32,
+        key: &str,
+        offset: u64,
+        length: u64,
+    ) -> Res<Box<dyn Read + Send>> {
+        let mut file = match fs::File::open(user_store_file(user_id, key)) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                return Err(StorageError::NotFound {
+                    user_id: user_id,
+                    key: key.into(),
+                }
+                .into())
+            }
+            Err(error) => return Err(error.into()),
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(file.take(length)))
+    }
+
+    fn delete(&self, user_id: i32, key: &str) -> Res<bool> {
+        match fs::remove_file(user_store_file(user_id, key)) {
+            Ok(()) => Ok(true),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn relocate(&self, user_id: i32, key: &str, to_user_id: i32) -> Res<()> {
+        fs::create_dir_all(user_store(to_user_id))?;
+        match fs::rename(
+            user_store_file(user_id, key),
+            user_store_file(to_user_id, key),
+        ) {
+            Ok(()) => Ok(()),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                Err(StorageError::NotFound {
+                    user_id: user_id,
+                    key: key.into(),
+                }
+                .into())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn stat(&self, user_id: i32, key: &str) -> Res<Option<Stat>> {
+        match fs::metadata(user_store_file(user_id, key)) {
+            Ok(metadata) => Ok(Some(Self::to_stat(user_id, key, &metadata))),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn list(&self) -> Res<Vec<Stat>> {
+        let mut stats = Vec::new();
+        if !store_dir().is_dir() {
+            return Ok(stats);
+        }
+        for user in fs::read_dir(store_dir())? {
+            let user = user?;
+            /* Only numeric directories belong to users. */
+            let user_id = match user.file_name().to_str().map(str::parse::<i32>) {
+                Some(Ok(user_id)) if user.file_type()?.is_dir() => user_id,
+                _ => continue,
+            };
+            for blob in fs::read_dir(user.path())? {
+                let blob = blob?;
+                let metadata = blob.metadata()?;
+                if !metadata.is_file() {
+                    continue;
+                }
+                if let Some(key) = blob.file_name().to_str() {
+                    stats.push(Self::to_stat(user_id, key, &metadata));
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
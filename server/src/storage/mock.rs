@@ -0,0 +1,293 @@
+//! An in-memory stand-in for an S3 endpoint, answering the requests
+//! `S3Storage` makes: object PUT, GET with ranges, HEAD and DELETE, and
+//! `ListObjectsV2` in pages of `PAGE_SIZE`.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const PAGE_SIZE: usize = 2;
+const LAST_MODIFIED: &'static str = "Sat, 17 Oct 2026 12:00:00 GMT";
+
+/// A request as the stand-in received it.
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    objects: BTreeMap<String, Vec<u8>>,
+    requests: Vec<Recorded>,
+}
+
+pub struct MockS3 {
+    pub endpoint: String,
+    pub bucket: String,
+    state: Arc<Mutex<State>>,
+}
+
+struct Reply {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+    /// HEAD advertises a length it doesn't send.
+    length: usize,
+}
+
+impl Reply {
+    fn new(status: &'static str, body: Vec<u8>) -> Self {
+        Reply {
+            status: status,
+            headers: Vec::new(),
+            length: body.len(),
+            body: body,
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::new(
+            "404 Not Found",
+            b"<Error><Code>NoSuchKey</Code></Error>".to_vec(),
+        )
+    }
+}
+
+impl MockS3 {
+    pub fn start(bucket: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("[mock] bind");
+        let endpoint = format!("http://{}", listener.local_addr().expect("[mock] addr"));
+        let state = Arc::new(Mutex::new(State::default()));
+        let shared = state.clone();
+        let bucket_path = format!("/{}", bucket);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let state = shared.clone();
+                let bucket = bucket_path.clone();
+                if let Ok(stream) = stream {
+                    thread::spawn(move || serve(stream, &bucket, &state));
+                }
+            }
+        });
+        MockS3 {
+            endpoint: endpoint,
+            bucket: bucket.into(),
+            state: state,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+/// Answer requests on one connection until the client closes it.
+fn serve(stream: TcpStream, bucket: &str, state: &Mutex<State>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let method = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("").to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).unwrap_or(0) == 0 {
+                return;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(colon) = header.find(':') {
+                headers.push((
+                    header[..colon].trim().to_string(),
+                    header[colon + 1..].trim().to_string(),
+                ));
+            }
+        }
+        let recorded = Recorded {
+            method: method,
+            target: target,
+            headers: headers,
+        };
+        let length = recorded
+            .header("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+
+        let reply = {
+            let mut state = state.lock().unwrap();
+            state.requests.push(recorded.clone());
+            handle(&recorded, body, bucket, &mut state.objects)
+        };
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\n",
+            reply.status, reply.length
+        );
+        for (name, value) in reply.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        if writer.write_all(head.as_bytes()).is_err() || writer.write_all(&reply.body).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle(
+    request: &Recorded,
+    body: Vec<u8>,
+    bucket: &str,
+    objects: &mut BTreeMap<String, Vec<u8>>,
+) -> Reply {
+    if !request.header("authorization").map_or(false, |auth| {
+        auth.starts_with("AWS4-HMAC-SHA256 Credential=")
+    }) {
+        return Reply::new(
+            "403 Forbidden",
+            b"<Error><Code>AccessDenied</Code></Error>".to_vec(),
+        );
+    }
+    let (path, query) = match request.target.find('?') {
+        Some(mark) => (&request.target[..mark], &request.target[mark + 1..]),
+        None => (request.target.as_str(), ""),
+    };
+    if path == bucket {
+        return list(query, objects);
+    }
+    let object = match path.starts_with(bucket) && path[bucket.len()..].starts_with('/') {
+        true => decode(&path[bucket.len() + 1..]),
+        false => return Reply::not_found(),
+    };
+    match request.method.as_str() {
+        "PUT" => {
+            objects.insert(object, body);
+            Reply::new("200 OK", Vec::new())
+        }
+        "DELETE" => {
+            objects.remove(&object);
+            Reply::new("204 No Content", Vec::new())
+        }
+        "HEAD" => match objects.get(&object) {
+            Some(data) => {
+                let mut reply = Reply::new("200 OK", Vec::new());
+                reply.length = data.len();
+                reply
+                    .headers
+                    .push(("Last-Modified", String::from(LAST_MODIFIED)));
+                reply
+            }
+            None => {
+                let mut reply = Reply::not_found();
+                reply.body.clear();
+                reply.length = 0;
+                reply
+            }
+        },
+        "GET" => match objects.get(&object) {
+            Some(data) => match request.header("range") {
+                Some(range) => {
+                    let range = range.trim_start_matches("bytes=");
+                    let mut bounds = range.splitn(2, '-');
+                    let start: usize = bounds.next().unwrap().parse().unwrap();
+                    let end: usize = bounds.next().unwrap().parse().unwrap();
+                    let end = end.min(data.len() - 1);
+                    Reply::new("206 Partial Content", data[start..=end].to_vec())
+                }
+                None => Reply::new("200 OK", data.clone()),
+            },
+            None => Reply::not_found(),
+        },
+        _ => Reply::new("405 Method Not Allowed", Vec::new()),
+    }
+}
+
+/// One page of `ListObjectsV2`; the continuation token is the last key sent.
+fn list(query: &str, objects: &BTreeMap<String, Vec<u8>>) -> Reply {
+    let mut prefix = String::new();
+    let mut after = None;
+    for pair in query.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("prefix"), Some(value)) => prefix = decode(value),
+            (Some("continuation-token"), Some(value)) => after = Some(decode(value)),
+            _ => {}
+        }
+    }
+    let matching: Vec<(&String, &Vec<u8>)> = objects
+        .iter()
+        .filter(|(name, _)| name.starts_with(&prefix))
+        .filter(|(name, _)| {
+            after
+                .as_ref()
+                .map_or(true, |after| name.as_str() > after.as_str())
+        })
+        .collect();
+    let page = &matching[..matching.len().min(PAGE_SIZE)];
+    let truncated = matching.len() > PAGE_SIZE;
+
+    let mut xml = String::from("<ListBucketResult>");
+    for (name, data) in page {
+        xml.push_str(&format!(
+            "<Contents><Key>{}</Key><LastModified>2026-10-17T12:00:00.000Z</LastModified>\
+             <Size>{}</Size></Contents>",
+            name.replace('&', "&amp;").replace('<', "&lt;"),
+            data.len()
+        ));
+    }
+    xml.push_str(&format!("<IsTruncated>{}</IsTruncated>", truncated));
+    if truncated {
+        if let Some((last, _)) = page.last() {
+            xml.push_str(&format!(
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                last.replace('&', "&amp;").replace('<', "&lt;")
+            ));
+        }
+    }
+    xml.push_str("</ListBucketResult>");
+    Reply::new("200 OK", xml.into_bytes())
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&value[i + 1..i + 3], 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,117 @@
+pub mod local;
+pub use self::local::LocalStorage;
+
+pub mod s3;
+pub use self::s3::S3Storage;
+
+#[cfg(test)]
+mod mock;
+
+use crate::config::{StorageConfig, StorageKind};
+use crate::util::error::Res;
+
+use chrono::NaiveDateTime;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+#[derive(Debug, Fail)]
+pub enum StorageError {
+    #[fail(display = "object not found: {}/{}", user_id, key)]
+    NotFound { user_id: i32, key: String },
+    #[fail(display = "remote error: {} {}", status, message)]
+    RemoteError { status: u16, message: String },
+    #[fail(display = "http error: {}", error)]
+    HttpError { error: reqwest::Error },
+    #[fail(display = "storage misconfigured: {}", message)]
+    ConfigError { message: String },
+    #[fail(
+        display = "{}/{} holds {} bytes, {} recorded",
+        user_id, key, stored, recorded
+    )]
+    SizeMismatch {
+        user_id: i32,
+        key: String,
+        stored: u64,
+        recorded: u64,
+    },
+}
+
+/// Metadata of a stored blob.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Stat {
+    pub user_id: i32,
+    pub key: String,
+    pub bytes: u64,
+    pub modified: Option<NaiveDateTime>,
+}
+
+/// Names of the copies `relocate` keeps in the spool root.
+pub const RELOCATE_PREFIX: &'static str = "relocate-";
+
+/// Blob storage, addressed by owner and file key.
+pub trait Storage: Send + Sync {
+    /// Move the local file at `source` into storage, returns the stored size.
+    fn put(&self, user_id: i32, key: &str, source: &Path) -> Res<u64>;
+
+    /// Open a blob for streaming.
+    fn get(&self, user_id: i32, key: &str) -> Res<Box<dyn Read + Send>>;
+
+    /// Open `length` bytes of a blob starting at `offset`.
+    fn get_range(
+        &self,
+        user_id: i32,
+        key: &str,
+        offset: u64,
+        length: u64,
+    ) -> Res<Box<dyn Read + Send>> {
+        let mut body = self.get(user_id, key)?;
+        io::copy(&mut (&mut body).take(offset), &mut io::sink())?;
+        Ok(Box::new(body.take(length)))
+    }
+
+    /// Remove a blob, returns `false` if there was nothing to remove.
+    fn delete(&self, user_id: i32, key: &str) -> Res<bool>;
+
+    /// Hand a blob over to another user, copying it through the spool.
+    fn relocate(&self, user_id: i32, key: &str, to_user_id: i32) -> Res<()> {
+        use crate::api::app::files::spool_dir;
+        use std::fs;
+
+        let temp = spool_dir().join(format!("{}{}-{}", RELOCATE_PREFIX, user_id, key));
+        let mut source = self.get(user_id, key)?;
+        let copied = fs::File::create(&temp).and_then(|mut file| io::copy(&mut source, &mut file));
+        if let Err(error) = copied {
+            let _ = fs::remove_file(&temp);
+            return Err(error.into());
+        }
+        /* `put` consumes the spooled copy. */
+        if let Err(error) = self.put(to_user_id, key, &temp) {
+            let _ = fs::remove_file(&temp);
+            return Err(error);
+        }
+        self.delete(user_id, key)?;
+        Ok(())
+    }
+
+    fn stat(&self, user_id: i32, key: &str) -> Res<Option<Stat>>;
+
+    /// Every blob in storage, regardless of owner.
+    fn list(&self) -> Res<Vec<Stat>>;
+}
+
+/// Build the backend selected in the configuration.
+pub fn open(config: &StorageConfig) -> Res<SharedStorage> {
+    match config.backend {
+        StorageKind::Local => Ok(Arc::new(LocalStorage)),
+        StorageKind::S3 => match config.s3 {
+            Some(ref s3) => Ok(Arc::new(S3Storage::new(s3.clone())?)),
+            None => Err(StorageError::ConfigError {
+                message: String::from("missing [storage.s3] section"),
+            }
+            .into()),
+        },
+    }
+}
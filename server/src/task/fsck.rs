@@ -0,0 +1,364 @@
+use crate::api::app::files::spool_dir;
+use crate::db;
+use crate::model::File;
+use crate::storage::{Stat, Storage, RELOCATE_PREFIX};
+use crate::util::{
+    error::{Error, Res, ServerError},
+    thumbnail,
+};
+
+use diesel::result::Error::NotFound;
+use fs2::FileExt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// A record whose blob is gone.
+    MissingBlob,
+    /// A blob without a record.
+    OrphanBlob,
+    /// A blob whose size differs from `files.bytes`.
+    SizeMismatch,
+    /// An abandoned upload or temporary file in the spool.
+    StaleSpool,
+    /// A thumbnail of a file that is gone.
+    OrphanThumbnail,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub user_id: Option<i32>,
+    pub key: Option<String>,
+    pub path: Option<PathBuf>,
+    /// Size according to the database.
+    pub expected: Option<i64>,
+    /// Size according to storage or the filesystem.
+    pub found: Option<u64>,
+    pub repaired: bool,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub files: usize,
+    pub blobs: usize,
+    pub spool: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Problems that are still there after the run.
+    pub fn remaining(&self) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| !problem.repaired)
+            .count()
+    }
+}
+
+/// Which problems to fix. Only orphan removal throws away data.
+#[derive(Debug, Clone, Copy)]
+pub struct Repair {
+    /// Drop records whose blob is gone.
+    pub missing: bool,
+    /// Correct `files.bytes` to the stored size.
+    pub sizes: bool,
+    /// Remove stale spool files and thumbnails of files that are gone.
+    pub spool: bool,
+    /// Delete blobs nobody owns.
+    pub orphans: bool,
+}
+
+impl Repair {
+    pub fn none() -> Self {
+        Repair {
+            missing: false,
+            sizes: false,
+            spool: false,
+            orphans: false,
+        }
+    }
+
+    pub fn safe() -> Self {
+        Repair {
+            missing: true,
+            sizes: true,
+            spool: true,
+            orphans: false,
+        }
+    }
+}
+
+/// Compares the `files` table, the storage backend and the spool.
+pub struct Fsck<'a> {
+    storage: &'a dyn Storage,
+    /// Spool files untouched for longer than this are considered abandoned.
+    spool_age: Duration,
+}
+
+impl<'a> Fsck<'a> {
+    pub fn new(storage: &'a dyn Storage, spool_age: Duration) -> Self {
+        Fsck { storage, spool_age }
+    }
+
+    pub fn run(&self, repair: Repair, c: &db::Connection) -> Res<Report> {
+        let mut report = Report::default();
+
+        /* Records are listed first: a blob stored after that shows up as an
+         * orphan, and every repair re-checks before it acts. */
+        let files = File::list(None, None, None, c)?;
+        let mut blobs: HashMap<(i32, String), Stat> = self
+            .storage
+            .list()?
+            .into_iter()
+            .map(|stat| ((stat.user_id, stat.key.clone()), stat))
+            .collect();
+        report.files = files.len();
+        report.blobs = blobs.len();
+        let keys: HashSet<String> = files.iter().map(|file| file.key.clone()).collect();
+
+        for mut file in files {
+            match blobs.remove(&(file.user_id, file.key.clone())) {
+                None => {
+                    let mut problem = Self::problem(ProblemKind::MissingBlob, &file);
+                    if repair.missing && self.storage.stat(file.user_id, &file.key)?.is_none() {
+                        File::delete(file.id, c)?;
+                        thumbnail::remove(&file.key);
+                        problem.repaired = true;
+                    }
+                    report.problems.push(problem);
+                }
+                Some(stat) => {
+                    if stat.bytes as i64 != file.bytes {
+                        let mut problem = Self::problem(ProblemKind::SizeMismatch, &file);
+                        problem.found = Some(stat.bytes);
+                        if repair.sizes {
+                            file.bytes = stat.bytes as i64;
+                            file.update(c)?;
+                            problem.repaired = true;
+                        }
+                        report.problems.push(problem);
+                    }
+                }
+            }
+        }
+
+        for ((user_id, key), stat) in blobs {
+            let mut problem = Problem {
+                kind: ProblemKind::OrphanBlob,
+                user_id: Some(user_id),
+                key: Some(key),
+                path: None,
+                expected: None,
+                found: Some(stat.bytes),
+                repaired: false,
+            };
+            if repair.orphans && !Self::recorded(user_id, &stat.key, c)? {
+                self.storage.delete(user_id, &stat.key)?;
+                problem.repaired = true;
+            }
+            report.problems.push(problem);
+        }
+
+        self.check_spool(repair, &mut report)?;
+        Self::check_thumbnails(&keys, repair, &mut report, c)?;
+        Ok(report)
+    }
+
+    fn problem(kind: ProblemKind, file: &File) -> Problem {
+        Problem {
+            kind: kind,
+            user_id: Some(file.user_id),
+            key: Some(file.key.clone()),
+            path: None,
+            expected: Some(file.bytes),
+            found: None,
+            repaired: false,
+        }
+    }
+
+    fn recorded(user_id: i32, key: &str, c: &db::Connection) -> Res<bool> {
+        /* Only a missing row makes a blob an orphan, a failing database must
+         * not get everything deleted. */
+        match File::by_key(key, c) {
+            Ok(file) => Ok(file.user_id == user_id),
+            Err(Error::ServerError(ServerError::DatabaseError { error: NotFound })) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Thumbnails are rendered in the background and can land after their
+    /// file was deleted.
+    fn check_thumbnails(
+        keys: &HashSet<String>,
+        repair: Repair,
+        report: &mut Report,
+        c: &db::Connection,
+    ) -> Res<()> {
+        if !thumbnail::thumb_dir().is_dir() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(thumbnail::thumb_dir())? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .map_or(true, |extension| extension != "jpg")
+            {
+                continue;
+            }
+            let key = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(key) if !keys.contains(key) => key.to_string(),
+                _ => continue,
+            };
+            /* Uploaded since the records were listed. */
+            match File::by_key(&key, c) {
+                Ok(_) => continue,
+                Err(Error::ServerError(ServerError::DatabaseError { error: NotFound })) => {}
+                Err(error) => return Err(error),
+            }
+            let mut problem = Problem {
+                kind: ProblemKind::OrphanThumbnail,
+                user_id: None,
+                key: Some(key.clone()),
+                path: Some(path),
+                expected: None,
+                found: None,
+                repaired: false,
+            };
+            if repair.spool {
+                thumbnail::remove(&key);
+                problem.repaired = true;
+            }
+            report.problems.push(problem);
+        }
+        Ok(())
+    }
+
+    fn check_spool(&self, repair: Repair, report: &mut Report) -> Res<()> {
+        if !spool_dir().is_dir() {
+            return Ok(());
+        }
+        let mut relocations = Vec::new();
+        for entry in fs::read_dir(spool_dir())? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() {
+                let user_id = name.parse().ok();
+                self.check_user_spool(&entry.path(), user_id, repair, report)?;
+            } else if name.starts_with(RELOCATE_PREFIX) {
+                relocations.push(entry.path());
+            }
+        }
+        /* Copies made while handing blobs to another user. */
+        for path in relocations {
+            self.check_spooled(None, &[path], repair, report)?;
+        }
+        Ok(())
+    }
+
+    /// A tus upload is `<id>.part` and `<id>.info`, and only the part is
+    /// written to while it runs, so the pair is judged together.
+    fn check_user_spool(
+        &self,
+        dir: &Path,
+        user_id: Option<i32>,
+        repair: Repair,
+        report: &mut Report,
+    ) -> Res<()> {
+        let mut uploads: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let id = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("part") | Some("info") => name[..name.len() - 5].to_string(),
+                _ => name,
+            };
+            uploads.entry(id).or_insert_with(Vec::new).push(path);
+        }
+        for paths in uploads.values() {
+            self.check_spooled(user_id, paths, repair, report)?;
+        }
+        Ok(())
+    }
+
+    /// Report, and with `repair.spool` remove, files that belong together
+    /// once the newest of them is older than `spool_age`. An upload whose
+    /// part is locked is being appended to and left alone.
+    fn check_spooled(
+        &self,
+        user_id: Option<i32>,
+        paths: &[PathBuf],
+        repair: Repair,
+        report: &mut Report,
+    ) -> Res<()> {
+        let now = SystemTime::now();
+        let mut files = Vec::new();
+        for path in paths {
+            /* Uploads finish while we look. */
+            match fs::metadata(path) {
+                Ok(ref metadata) if !metadata.is_file() => {}
+                Ok(metadata) => files.push((path, metadata)),
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        report.spool += files.len();
+        let age = files
+            .iter()
+            .map(|(_, metadata)| {
+                metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| now.duration_since(modified).ok())
+                    .unwrap_or_default()
+            })
+            .min();
+        match age {
+            Some(age) if age >= self.spool_age => {}
+            _ => return Ok(()),
+        }
+        /* Held until the files are gone, so no append starts meanwhile. */
+        let mut locks = Vec::new();
+        for (path, _) in &files {
+            if path
+                .extension()
+                .map_or(false, |extension| extension == "part")
+            {
+                let part = match fs::OpenOptions::new().append(true).open(path) {
+                    Ok(part) => part,
+                    Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+                    Err(error) => return Err(error.into()),
+                };
+                if part.try_lock_exclusive().is_err() {
+                    return Ok(());
+                }
+                locks.push(part);
+            }
+        }
+        for (path, metadata) in files {
+            let mut problem = Problem {
+                kind: ProblemKind::StaleSpool,
+                user_id: user_id,
+                key: None,
+                path: Some(path.clone()),
+                expected: None,
+                found: Some(metadata.len()),
+                repaired: false,
+            };
+            if repair.spool {
+                fs::remove_file(path)?;
+                problem.repaired = true;
+            }
+            report.problems.push(problem);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use crate::api::app::files::{spool_dir, store_dir};
+use crate::config::{self, StorageKind};
+use crate::db::{self, connection::SqlitePool};
+use crate::util::random::random_ascii;
+
+use diesel::{
+    prelude::*,
+    sql_types::{Integer, Nullable, Text},
+};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the filesystem checks are reused. The health endpoints are
+/// unauthenticated, this keeps them from writing a file on every request.
+const FILESYSTEM_TTL: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref FILESYSTEM: Mutex<Option<(Instant, Vec<Check>)>> = Mutex::new(None);
+}
+
+/// Whether one aspect of the installation is usable.
+#[derive(Serialize, Clone, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// What is wrong, or what was found.
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Health {
+    pub checks: Vec<Check>,
+}
+
+#[derive(QueryableByName)]
+struct SchemaVersion {
+    #[sql_type = "Nullable<Text>"]
+    version: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct ForeignKeys {
+    #[sql_type = "Integer"]
+    foreign_keys: i32,
+}
+
+impl Health {
+    /// Run every check. Nothing is changed but a probe file in each
+    /// directory that has to be writable.
+    pub fn check(pool: &SqlitePool) -> Self {
+        let mut checks = Self::filesystem();
+        match pool.get() {
+            Ok(c) => {
+                let c = db::Connection(c);
+                checks.push(Self::schema(&c));
+                checks.push(Self::foreign_keys(&c));
+            }
+            Err(error) => checks.push(Check::fail("database", error.to_string())),
+        }
+        Health { checks }
+    }
+
+    /// The filesystem checks, when the database can't even be opened.
+    pub fn unreachable(error: String) -> Self {
+        let mut checks = Self::filesystem();
+        checks.push(Check::fail("database", error));
+        Health { checks }
+    }
+
+    fn filesystem() -> Vec<Check> {
+        /* Holding the lock while checking also keeps concurrent requests
+         * from probing at the same time. */
+        let mut cached = FILESYSTEM.lock().expect("[health] lock poisoned");
+        match *cached {
+            Some((at, ref checks)) if at.elapsed() < FILESYSTEM_TTL => checks.clone(),
+            _ => {
+                let checks = vec![Self::directories(), Self::writable(), Self::free_space()];
+                *cached = Some((Instant::now(), checks.clone()));
+                checks
+            }
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.checks.iter().all(|check| check.ok)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.ok)
+    }
+
+    /// Directories that have to exist; blobs only live below `dir` locally.
+    fn local_dirs() -> Vec<std::path::PathBuf> {
+        let config = config::get();
+        let mut dirs = vec![config.dir.clone(), spool_dir()];
+        if config.storage.backend == StorageKind::Local {
+            dirs.push(store_dir());
+        }
+        dirs
+    }
+
+    fn directories() -> Check {
+        let missing: Vec<String> = Self::local_dirs()
+            .iter()
+            .filter(|dir| !dir.is_dir())
+            .map(|dir| dir.display().to_string())
+            .collect();
+        match missing.is_empty() {
+            true => Check::pass("directories", "present"),
+            false => Check::fail("directories", format!("missing: {}", missing.join(", "))),
+        }
+    }
+
+    fn writable() -> Check {
+        let config = config::get();
+        let dirs = match config.storage.backend {
+            StorageKind::Local => vec![spool_dir(), store_dir()],
+            StorageKind::S3 => vec![spool_dir()],
+        };
+        for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+            if let Err(error) = Self::probe(dir) {
+                return Check::fail(
+                    "permissions",
+                    format!("{} is not writable: {}", dir.display(), error),
+                );
+            }
+        }
+        Check::pass("permissions", "writable")
+    }
+
+    fn probe(dir: &Path) -> std::io::Result<()> {
+        /* Unique, so that another process on the same directories doesn't
+         * remove the probe under us. */
+        let path = dir.join(format!(
+            ".flink-probe-{}-{}",
+            std::process::id(),
+            random_ascii(8)
+        ));
+        fs::write(&path, b"probe")?;
+        fs::remove_file(&path)
+    }
+
+    fn free_space() -> Check {
+        let config = config::get();
+        match fs2::available_space(&config.dir) {
+            Ok(free) if free >= config.min_free_space => {
+                Check::pass("disk space", format!("{} bytes free", free))
+            }
+            Ok(free) => Check::fail(
+                "disk space",
+                format!(
+                    "{} bytes free, below min_free_space of {}",
+                    free, config.min_free_space
+                ),
+            ),
+            Err(error) => Check::fail("disk space", error.to_string()),
+        }
+    }
+
+    fn schema(c: &db::Connection) -> Check {
+        let found =
+            diesel::sql_query("SELECT MAX(version) AS version FROM __diesel_schema_migrations")
+                .get_result::<SchemaVersion>(&**c);
+        match found {
+            Ok(SchemaVersion {
+                version: Some(ref version),
+            }) if version == db::SCHEMA_VERSION => Check::pass("schema", version.clone()),
+            Ok(SchemaVersion {
+                version: Some(version),
+            }) => Check::fail(
+                "schema",
+                format!(
+                    "database is at {}, expected {}; run the migrations",
+                    version,
+                    db::SCHEMA_VERSION
+                ),
+            ),
+            Ok(SchemaVersion { version: None }) => {
+                Check::fail("schema", "no migrations applied".to_string())
+            }
+            Err(error) => Check::fail("schema", error.to_string()),
+        }
+    }
+
+    fn foreign_keys(c: &db::Connection) -> Check {
+        match diesel::sql_query("PRAGMA foreign_keys").get_result::<ForeignKeys>(&**c) {
+            Ok(ForeignKeys { foreign_keys: 1 }) => Check::pass("foreign keys", "enabled"),
+            Ok(_) => Check::fail("foreign keys", "disabled".to_string()),
+            Err(error) => Check::fail("foreign keys", error.to_string()),
+        }
+    }
+}
+
+impl Check {
+    fn pass<S: Into<String>>(name: &'static str, message: S) -> Self {
+        Check {
+            name: name,
+            ok: true,
+            message: message.into(),
+        }
+    }
+
+    fn fail(name: &'static str, message: String) -> Self {
+        Check {
+            name: name,
+            ok: false,
+            message: message,
+        }
+    }
+}
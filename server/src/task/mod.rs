@@ -0,0 +1,14 @@
+pub mod reaper;
+pub use self::reaper::Reaper;
+
+pub mod fsck;
+pub use self::fsck::Fsck;
+
+pub mod usage;
+pub use self::usage::UsageSampler;
+
+pub mod health;
+pub use self::health::Health;
+
+pub mod thumbnails;
+pub use self::thumbnails::Thumbnailer;
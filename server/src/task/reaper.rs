@@ -0,0 +1,72 @@
+use crate::db::{self, connection::SqlitePool};
+use crate::model::{File, Session};
+use crate::storage::SharedStorage;
+use crate::util::{date::UtcDateTime, error::Res, thumbnail};
+
+use chrono::prelude::*;
+use std::{thread, time::Duration};
+
+/// Periodically removes files whose `delete_date` has passed, or that have
+/// been downloaded as often as they may be, and sessions that timed out.
+pub struct Reaper {
+    pool: SqlitePool,
+    storage: SharedStorage,
+    interval: Duration,
+}
+
+impl Reaper {
+    pub fn new(pool: SqlitePool, storage: SharedStorage, interval: Duration) -> Self {
+        Self {
+            pool,
+            storage,
+            interval,
+        }
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        info!("running every {}s", self.interval.as_secs());
+        thread::Builder::new()
+            .name(String::from("reaper"))
+            .spawn(move || loop {
+                match self.reap() {
+                    Ok(0) => {}
+                    Ok(count) => info!("removed {} expired file(s)", count),
+                    Err(error) => error!("sweep failed: {}", error),
+                }
+                thread::sleep(self.interval);
+            })
+            .expect("[reaper] failed to spawn thread")
+    }
+
+    pub fn reap(&self) -> Res<usize> {
+        let c = db::Connection(self.pool.get()?);
+        let now = UtcDateTime(Utc::now()).into();
+        let mut count = 0;
+
+        for file in File::reapable(now, &c)? {
+            /* A blob that is already gone shouldn't keep the record alive. */
+            match self.storage.delete(file.user_id, &file.key) {
+                Ok(true) => {}
+                Ok(false) => warn!("{} already missing in storage", file),
+                Err(error) => {
+                    error!("failed to remove {}: {:?}", file, error);
+                    continue;
+                }
+            }
+            File::delete(file.id, &c)?;
+            thumbnail::remove(&file.key);
+            info!(
+                "removed {} ({} bytes, expires {}, {}/{:?} downloads)",
+                file, file.bytes, file.delete_date, file.downloads, file.max_downloads
+            );
+            count += 1;
+        }
+
+        let sessions = Session::delete_expired(now, &c)?;
+        if sessions > 0 {
+            info!("removed {} expired session(s)", sessions);
+        }
+
+        Ok(count)
+    }
+}
@@ -0,0 +1,48 @@
+use crate::config;
+use crate::model::File;
+use crate::storage::SharedStorage;
+use crate::util::thumbnail;
+
+use std::sync::{
+    mpsc::{channel, Sender},
+    Mutex,
+};
+use std::thread;
+
+/// Renders thumbnails of new uploads one after another, so the upload
+/// itself doesn't wait for image decoding.
+pub struct Thumbnailer {
+    sender: Mutex<Sender<(i32, String)>>,
+}
+
+impl Thumbnailer {
+    pub fn spawn(storage: SharedStorage) -> Self {
+        let (sender, receiver) = channel::<(i32, String)>();
+        thread::Builder::new()
+            .name(String::from("thumbnails"))
+            .spawn(move || {
+                for (user_id, key) in receiver {
+                    match thumbnail::generate(user_id, &key, &*storage) {
+                        Ok(true) => debug!("created thumbnail of {}", key),
+                        Ok(false) => {}
+                        Err(error) => warn!("no thumbnail for {}: {}", key, error),
+                    }
+                }
+            })
+            .expect("[thumbnails] failed to spawn thread");
+        Thumbnailer {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    pub fn queue(&self, file: &File) {
+        if !config::get().thumbnails.enabled {
+            return;
+        }
+        if let Ok(sender) = self.sender.lock() {
+            if sender.send((file.user_id, file.key.clone())).is_err() {
+                warn!("thumbnail worker is gone, skipping {}", file.key);
+            }
+        }
+    }
+}
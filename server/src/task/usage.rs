@@ -0,0 +1,37 @@
+use crate::db::{self, connection::SqlitePool};
+use crate::model::Usage;
+use crate::util::{error::Res, metrics};
+
+use std::{thread, time::Duration};
+
+/// Periodically counts the files and bytes of every user for `/metrics`,
+/// so scrapes don't have to scan the files table.
+pub struct UsageSampler {
+    pool: SqlitePool,
+    interval: Duration,
+}
+
+impl UsageSampler {
+    pub fn new(pool: SqlitePool, interval: Duration) -> Self {
+        Self { pool, interval }
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        info!("sampling usage every {}s", self.interval.as_secs());
+        thread::Builder::new()
+            .name(String::from("usage"))
+            .spawn(move || loop {
+                if let Err(error) = self.sample() {
+                    error!("sampling failed: {}", error);
+                }
+                thread::sleep(self.interval);
+            })
+            .expect("[usage] failed to spawn thread")
+    }
+
+    pub fn sample(&self) -> Res<()> {
+        let c = db::Connection(self.pool.get()?);
+        metrics::get().set_usage(Usage::all(&c)?);
+        Ok(())
+    }
+}
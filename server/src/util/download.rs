@@ -1,18 +1,470 @@
+use crate::model::File;
+use crate::storage::{Storage, StorageError};
+use crate::util::{
+    date::UtcDateTime, error::Res, filename, mime, random::random_ascii, unlock::UnlockForm,
+};
+
 use rocket::{
-    response::{self, NamedFile, Responder, Response},
-    Request,
+    http::Status,
+    request::{self, FromRequest},
+    response::{self, Body, Responder, Response},
+    Outcome, Request,
 };
+use std::io::{self, Cursor, Read};
+
+/// More ranges than this are answered with the whole file.
+const MAX_RANGES: usize = 16;
+const BOUNDARY_LEN: usize = 24;
+
+/// The `Range` and `If-Range` headers of a download request.
+pub struct RangeHeaders {
+    pub range: Option<String>,
+    pub if_range: Option<String>,
+}
 
-pub struct FileDownload(pub NamedFile, pub String);
+impl<'a, 'r> FromRequest<'a, 'r> for RangeHeaders {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let headers = request.headers();
+        Outcome::Success(RangeHeaders {
+            range: headers.get_one("Range").map(String::from),
+            if_range: headers.get_one("If-Range").map(String::from),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    Full,
+    /// Inclusive byte ranges, all within the file.
+    Partial(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+impl Ranges {
+    /// Parse a `Range` header against a file of `size` bytes. Anything we
+    /// don't understand is ignored, as RFC 7233 allows.
+    pub fn parse(header: &str, size: u64) -> Self {
+        let header = header.trim();
+        if !header.starts_with("bytes=") {
+            return Ranges::Full;
+        }
+        let mut ranges = Vec::new();
+        for spec in header["bytes=".len()..].split(',') {
+            let mut parts = spec.trim().splitn(2, '-');
+            let (first, last) = match (parts.next(), parts.next()) {
+                (Some(first), Some(last)) => (first.trim(), last.trim()),
+                _ => return Ranges::Full,
+            };
+            if first.is_empty() {
+                let suffix: u64 = match last.parse() {
+                    Ok(suffix) => suffix,
+                    Err(_) => return Ranges::Full,
+                };
+                if suffix == 0 || size == 0 {
+                    continue;
+                }
+                ranges.push((size.saturating_sub(suffix), size - 1));
+            } else {
+                let start: u64 = match first.parse() {
+                    Ok(start) => start,
+                    Err(_) => return Ranges::Full,
+                };
+                let end = match last {
+                    "" => u64::max_value(),
+                    last => match last.parse() {
+                        Ok(end) if end >= start => end,
+                        _ => return Ranges::Full,
+                    },
+                };
+                if start >= size {
+                    continue;
+                }
+                ranges.push((start, end.min(size - 1)));
+            }
+        }
+        match ranges.len() {
+            0 => Ranges::Unsatisfiable,
+            n if n > MAX_RANGES => Ranges::Full,
+            _ => Ranges::Partial(ranges),
+        }
+    }
+
+    /// Work out what to send, honouring `If-Range` against the validators.
+    pub fn from_headers(headers: &RangeHeaders, size: u64, etag: &str, modified: &str) -> Self {
+        let range = match headers.range {
+            Some(ref range) => range,
+            None => return Ranges::Full,
+        };
+        if let Some(ref if_range) = headers.if_range {
+            let if_range = if_range.trim();
+            let fresh = match if_range.starts_with('"') {
+                true => if_range == etag,
+                false => if_range == modified,
+            };
+            if !fresh {
+                return Ranges::Full;
+            }
+        }
+        Self::parse(range, size)
+    }
+}
+
+/// A stored blob streamed back under its original name.
+pub struct FileDownload {
+    pub status: Status,
+    pub body: Box<dyn Read + Send>,
+    /// Length of `body`, sent as `Content-Length`.
+    pub length: u64,
+    pub name: String,
+    /// The type of the file, `content_type` is multipart for several ranges.
+    pub mime: String,
+    pub content_type: String,
+    /// Shown in the browser rather than saved, for safe types only.
+    pub inline: bool,
+    pub content_range: Option<String>,
+    pub etag: String,
+    pub last_modified: String,
+}
+
+impl FileDownload {
+    pub fn etag(file: &File) -> String {
+        format!("\"{:x}-{:x}\"", file.upload_date.timestamp(), file.bytes)
+    }
+
+    pub fn last_modified(file: &File) -> String {
+        UtcDateTime::from(file.upload_date)
+            .0
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    }
+
+    fn new(file: &File, status: Status, body: Box<dyn Read + Send>, length: u64) -> Self {
+        let mime = file
+            .content_type
+            .clone()
+            .unwrap_or_else(|| String::from(mime::OCTET_STREAM));
+        FileDownload {
+            status: status,
+            body: body,
+            length: length,
+            name: file.val.clone(),
+            content_type: mime.clone(),
+            mime: mime,
+            inline: false,
+            content_range: None,
+            etag: Self::etag(file),
+            last_modified: Self::last_modified(file),
+        }
+    }
+
+    /// The size of the stored blob. Lengths and ranges are worked out from
+    /// the record, so a blob that differs is refused rather than sent with
+    /// a `Content-Length` it can't fill.
+    fn stored_size(file: &File, storage: &dyn Storage) -> Res<u64> {
+        let recorded = file.bytes as u64;
+        match storage.stat(file.user_id, &file.key)? {
+            Some(ref stat) if stat.bytes == recorded => Ok(recorded),
+            Some(stat) => Err(StorageError::SizeMismatch {
+                user_id: file.user_id,
+                key: file.key.clone(),
+                stored: stat.bytes,
+                recorded: recorded,
+            }
+            .into()),
+            None => Err(StorageError::NotFound {
+                user_id: file.user_id,
+                key: file.key.clone(),
+            }
+            .into()),
+        }
+    }
+
+    /// Headers only; the empty body keeps the advertised length for HEAD.
+    pub fn head(file: &File, storage: &dyn Storage) -> Res<Self> {
+        let size = Self::stored_size(file, storage)?;
+        Ok(Self::new(file, Status::Ok, Box::new(io::empty()), size))
+    }
+
+    pub fn open(file: &File, ranges: &Ranges, storage: &dyn Storage) -> Res<Self> {
+        let size = Self::stored_size(file, storage)?;
+        match ranges {
+            Ranges::Full => {
+                let body = storage.get(file.user_id, &file.key)?;
+                Ok(Self::new(file, Status::Ok, body, size))
+            }
+            Ranges::Unsatisfiable => {
+                let mut download =
+                    Self::new(file, Status::RangeNotSatisfiable, Box::new(io::empty()), 0);
+                download.content_range = Some(format!("bytes */{}", size));
+                Ok(download)
+            }
+            Ranges::Partial(ranges) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+                let body = storage.get_range(file.user_id, &file.key, start, end - start + 1)?;
+                let mut download = Self::new(file, Status::PartialContent, body, end - start + 1);
+                download.content_range = Some(format!("bytes {}-{}/{}", start, end, size));
+                Ok(download)
+            }
+            Ranges::Partial(ranges) => {
+                let boundary = random_ascii(BOUNDARY_LEN);
+                let mime = file
+                    .content_type
+                    .as_ref()
+                    .map_or(mime::OCTET_STREAM, String::as_str);
+                let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+                let mut length = 0;
+                for &(start, end) in ranges {
+                    let part = format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, mime, start, end, size
+                    );
+                    let data =
+                        storage.get_range(file.user_id, &file.key, start, end - start + 1)?;
+                    length += part.len() as u64 + end - start + 1;
+                    body = Box::new(body.chain(Cursor::new(part.into_bytes())).chain(data));
+                }
+                let tail = format!("\r\n--{}--\r\n", boundary);
+                length += tail.len() as u64;
+                body = Box::new(body.chain(Cursor::new(tail.into_bytes())));
+
+                let mut download = Self::new(file, Status::PartialContent, body, length);
+                download.content_type = format!("multipart/byteranges; boundary={}", boundary);
+                Ok(download)
+            }
+        }
+    }
+
+    /// Ask the browser to show the file, if it can do so without running
+    /// anything from it. Other files stay attachments.
+    pub fn inline(mut self) -> Self {
+        self.inline = mime::is_inline_safe(&self.mime);
+        self
+    }
+
+    /// Whether this response counts as a download of a file of `size`
+    /// bytes: it hands out the whole file, or the rest of it to a client
+    /// resuming with a matching `If-Range`. Probes and media players
+    /// seeking around, the end included, don't count.
+    pub fn is_download(ranges: &Ranges, headers: &RangeHeaders, size: u64) -> bool {
+        /* `from_headers` only returns ranges for a fresh `If-Range`. */
+        let resumed = headers.if_range.is_some();
+        match ranges {
+            Ranges::Full => true,
+            Ranges::Partial(ranges) => ranges
+                .iter()
+                .any(|&(start, end)| end + 1 == size && (start == 0 || resumed)),
+            Ranges::Unsatisfiable => false,
+        }
+    }
+}
 
 impl Responder<'_> for FileDownload {
-    fn respond_to(self, req: &Request<'_>) -> response::Result<'static> {
-        Response::build()
-            .merge(self.0.respond_to(req)?)
+    fn respond_to(self, _req: &Request<'_>) -> response::Result<'static> {
+        let disposition = match self.inline {
+            true => "inline",
+            false => "attachment",
+        };
+        let mut response = Response::build();
+        response
+            .status(self.status)
+            .raw_header("Content-Type", self.content_type)
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("ETag", self.etag)
+            .raw_header("Last-Modified", self.last_modified)
             .raw_header(
                 "Content-Disposition",
-                format!("attachment; filename=\"{}\"", self.1),
+                filename::content_disposition(disposition, &self.name),
             )
-            .ok()
+            .raw_body(Body::Sized(self.body, self.length));
+        /* Should a browser render active content anyway, it gets no
+         * scripts and no access to our origin. */
+        if !mime::is_inline_safe(&self.mime) {
+            response.raw_header("Content-Security-Policy", "sandbox; default-src 'none'");
+        }
+        if let Some(content_range) = self.content_range {
+            response.raw_header("Content-Range", content_range);
+        }
+        response.ok()
+    }
+}
+
+/// The answer to a share link: the file, or a form asking for its password.
+pub enum Lookup {
+    Download(FileDownload),
+    Locked(UnlockForm),
+}
+
+impl Responder<'_> for Lookup {
+    fn respond_to(self, req: &Request<'_>) -> response::Result<'static> {
+        match self {
+            Lookup::Download(download) => download.respond_to(req),
+            Lookup::Locked(form) => form.respond_to(req),
+        }
+    }
+}
+
+impl Lookup {
+    /// The same answer for `/view`: shown inline, or asking for the
+    /// password to view rather than download.
+    pub fn inline(self) -> Self {
+        match self {
+            Lookup::Download(download) => Lookup::Download(download.inline()),
+            Lookup::Locked(form) => Lookup::Locked(UnlockForm { view: true, ..form }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(ranges: &[(u64, u64)]) -> Ranges {
+        Ranges::Partial(ranges.to_vec())
+    }
+
+    #[test]
+    fn parses_simple_ranges() {
+        assert_eq!(Ranges::parse("bytes=0-499", 1000), partial(&[(0, 499)]));
+        assert_eq!(Ranges::parse("bytes=500-", 1000), partial(&[(500, 999)]));
+        assert_eq!(
+            Ranges::parse("bytes=990-2000", 1000),
+            partial(&[(990, 999)])
+        );
+        assert_eq!(
+            Ranges::parse(" bytes= 0-1 , 3-4 ", 1000),
+            partial(&[(0, 1), (3, 4)])
+        );
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(Ranges::parse("bytes=-200", 1000), partial(&[(800, 999)]));
+        assert_eq!(Ranges::parse("bytes=-2000", 1000), partial(&[(0, 999)]));
+        assert_eq!(Ranges::parse("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(
+            Ranges::parse("bytes=0-0,-1", 1000),
+            partial(&[(0, 0), (999, 999)])
+        );
+    }
+
+    #[test]
+    fn keeps_overlapping_ranges() {
+        assert_eq!(
+            Ranges::parse("bytes=0-10,5-15", 1000),
+            partial(&[(0, 10), (5, 15)])
+        );
+    }
+
+    #[test]
+    fn ignores_what_it_does_not_understand() {
+        assert_eq!(Ranges::parse("items=0-1", 1000), Ranges::Full);
+        assert_eq!(Ranges::parse("bytes=a-b", 1000), Ranges::Full);
+        assert_eq!(Ranges::parse("bytes=5", 1000), Ranges::Full);
+        assert_eq!(Ranges::parse("bytes=500-400", 1000), Ranges::Full);
+        assert_eq!(Ranges::parse("bytes=0-1,x", 1000), Ranges::Full);
+        let many = (0..=MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 2, i * 2))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(
+            Ranges::parse(&format!("bytes={}", many), 1000),
+            Ranges::Full
+        );
+    }
+
+    #[test]
+    fn rejects_ranges_beyond_the_end() {
+        assert_eq!(Ranges::parse("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(
+            Ranges::parse("bytes=1000-1001", 1000),
+            Ranges::Unsatisfiable
+        );
+        assert_eq!(Ranges::parse("bytes=2000-,0-1", 1000), partial(&[(0, 1)]));
+        assert_eq!(Ranges::parse("bytes=0-", 0), Ranges::Unsatisfiable);
+        assert_eq!(Ranges::parse("bytes=-5", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn honours_if_range() {
+        let headers = |if_range: Option<&str>| RangeHeaders {
+            range: Some(String::from("bytes=0-9")),
+            if_range: if_range.map(String::from),
+        };
+        let etag = "\"5d-3e8\"";
+        let modified = "Sat, 17 Oct 2026 12:00:00 GMT";
+        let ranges = |if_range| Ranges::from_headers(&headers(if_range), 1000, etag, modified);
+        assert_eq!(ranges(None), partial(&[(0, 9)]));
+        assert_eq!(ranges(Some(etag)), partial(&[(0, 9)]));
+        assert_eq!(ranges(Some(modified)), partial(&[(0, 9)]));
+        assert_eq!(ranges(Some("\"other\"")), Ranges::Full);
+        assert_eq!(ranges(Some("Fri, 16 Oct 2026 12:00:00 GMT")), Ranges::Full);
+
+        let none = RangeHeaders {
+            range: None,
+            if_range: None,
+        };
+        assert_eq!(
+            Ranges::from_headers(&none, 1000, etag, modified),
+            Ranges::Full
+        );
+    }
+
+    fn headers(range: &str, if_range: Option<&str>) -> RangeHeaders {
+        RangeHeaders {
+            range: Some(String::from(range)),
+            if_range: if_range.map(String::from),
+        }
+    }
+
+    #[test]
+    fn counts_complete_downloads_only() {
+        let plain = headers("bytes=0-", None);
+        let counts =
+            |ranges: &[(u64, u64)]| FileDownload::is_download(&partial(ranges), &plain, 1000);
+        assert!(FileDownload::is_download(&Ranges::Full, &plain, 1000));
+        assert!(counts(&[(0, 999)]));
+        assert!(!counts(&[(0, 0)]));
+        assert!(!counts(&[(0, 499)]));
+        /* Seeking to the end isn't finishing a download. */
+        assert!(!counts(&[(500, 999)]));
+        assert!(!counts(&[(0, 0), (999, 999)]));
+        assert!(!FileDownload::is_download(
+            &Ranges::Unsatisfiable,
+            &plain,
+            1000
+        ));
+    }
+
+    #[test]
+    fn does_not_count_suffix_probes() {
+        let probe = headers("bytes=-1", None);
+        let ranges = Ranges::from_headers(&probe, 1000, "\"e\"", "m");
+        assert_eq!(ranges, partial(&[(999, 999)]));
+        assert!(!FileDownload::is_download(&ranges, &probe, 1000));
+
+        let tail = headers("bytes=-200", None);
+        let ranges = Ranges::from_headers(&tail, 1000, "\"e\"", "m");
+        assert!(!FileDownload::is_download(&ranges, &tail, 1000));
+    }
+
+    #[test]
+    fn counts_resumed_downloads() {
+        let resume = headers("bytes=500-", Some("\"e\""));
+        let ranges = Ranges::from_headers(&resume, 1000, "\"e\"", "m");
+        assert_eq!(ranges, partial(&[(500, 999)]));
+        assert!(FileDownload::is_download(&ranges, &resume, 1000));
+
+        /* A stale validator gets the whole file, which counts anyway. */
+        let stale = headers("bytes=500-", Some("\"old\""));
+        let ranges = Ranges::from_headers(&stale, 1000, "\"e\"", "m");
+        assert_eq!(ranges, Ranges::Full);
+        assert!(FileDownload::is_download(&ranges, &stale, 1000));
+
+        let partway = headers("bytes=500-599", Some("\"e\""));
+        let ranges = Ranges::from_headers(&partway, 1000, "\"e\"", "m");
+        assert!(!FileDownload::is_download(&ranges, &partway, 1000));
     }
 }
@@ -20,6 +20,10 @@ pub enum ServerError {
     SerializationError { error: serde_json::error::Error },
     #[fail(display = "database error: {}", error)]
     DatabaseError { error: diesel::result::Error },
+    #[fail(display = "connection pool error: {}", error)]
+    PoolError { error: diesel::r2d2::PoolError },
+    #[fail(display = "storage error: {}", error)]
+    StorageError { error: crate::storage::StorageError },
 }
 
 #[derive(Debug, Fail)]
@@ -44,6 +48,16 @@ pub enum ClientError {
     FileError {
         error: crate::api::app::files::FileError,
     },
+    #[fail(display = "tus: {}", error)]
+    TusError { error: crate::util::tus::TusError },
+    #[fail(display = "quota: {}", error)]
+    QuotaError {
+        error: crate::model::user::quota::QuotaError,
+    },
+    #[fail(display = "token: {}", error)]
+    TokenError {
+        error: crate::model::user::token::TokenError,
+    },
 }
 
 #[derive(Debug)]
@@ -52,6 +66,15 @@ pub enum Error {
     ServerError(ServerError),
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::ClientError(error) => write!(f, "{}", error),
+            Error::ServerError(error) => write!(f, "{}", error),
+        }
+    }
+}
+
 impl From<ServerError> for Error {
     fn from(error: ServerError) -> Self {
         Error::ServerError(error)
@@ -112,6 +135,42 @@ impl From<FileError> for ClientError {
     }
 }
 
+use crate::model::user::quota::QuotaError;
+impl From<QuotaError> for Error {
+    fn from(error: QuotaError) -> Self {
+        Error::ClientError(error.into())
+    }
+}
+impl From<QuotaError> for ClientError {
+    fn from(error: QuotaError) -> Self {
+        ClientError::QuotaError { error }
+    }
+}
+
+use crate::model::user::token::TokenError;
+impl From<TokenError> for Error {
+    fn from(error: TokenError) -> Self {
+        Error::ClientError(error.into())
+    }
+}
+impl From<TokenError> for ClientError {
+    fn from(error: TokenError) -> Self {
+        ClientError::TokenError { error }
+    }
+}
+
+use crate::util::tus::TusError;
+impl From<TusError> for Error {
+    fn from(error: TusError) -> Self {
+        Error::ClientError(error.into())
+    }
+}
+impl From<TusError> for ClientError {
+    fn from(error: TusError) -> Self {
+        ClientError::TusError { error }
+    }
+}
+
 use crate::util::multipart::MultipartError;
 impl From<MultipartError> for Error {
     fn from(error: MultipartError) -> Self {
@@ -136,6 +195,30 @@ impl From<DieselError> for ServerError {
     }
 }
 
+use crate::storage::StorageError;
+impl From<StorageError> for Error {
+    fn from(error: StorageError) -> Self {
+        Error::ServerError(error.into())
+    }
+}
+impl From<StorageError> for ServerError {
+    fn from(error: StorageError) -> Self {
+        ServerError::StorageError { error }
+    }
+}
+
+use diesel::r2d2::PoolError;
+impl From<PoolError> for Error {
+    fn from(error: PoolError) -> Self {
+        Error::ServerError(error.into())
+    }
+}
+impl From<PoolError> for ServerError {
+    fn from(error: PoolError) -> Self {
+        ServerError::PoolError { error }
+    }
+}
+
 impl From<std::string::FromUtf8Error> for ServerError {
     fn from(error: std::string::FromUtf8Error) -> Self {
         ServerError::Utf8Error {
@@ -192,7 +275,8 @@ impl ErrorInfo for SessionError {
     fn info(&self) -> (i32, Status) {
         match self {
             SessionError::CookieNotFound {} => (110, Status::NotFound),
-            SessionError::RecordNotFound { token } => (111, Status::NotFound),
+            SessionError::RecordNotFound {} => (111, Status::NotFound),
+            SessionError::NotFound { id } => (112, Status::NotFound),
         }
     }
 }
@@ -202,10 +286,7 @@ impl ErrorInfo for RegistrationError {
     fn info(&self) -> (i32, Status) {
         match self {
             RegistrationError::DuplicateUsername { username } => (120, Status::Conflict),
-            RegistrationError::PasswordMismatch {
-                password_one,
-                password_two,
-            } => (121, Status::UnprocessableEntity),
+            RegistrationError::PasswordMismatch {} => (121, Status::UnprocessableEntity),
         }
     }
 }
@@ -214,12 +295,10 @@ impl ErrorInfo for AuthenticationError {
     #[allow(unused_variables)]
     fn info(&self) -> (i32, Status) {
         match self {
-            AuthenticationError::InvalidUsername { username, password } => {
-                (130, Status::UnprocessableEntity)
-            }
-            AuthenticationError::InvalidPassword { username, password } => {
+            AuthenticationError::InvalidCredentials { username } => {
                 (130, Status::UnprocessableEntity)
             }
+            AuthenticationError::Throttled { seconds } => (131, Status::TooManyRequests),
         }
     }
 }
@@ -241,6 +320,50 @@ impl ErrorInfo for FileError {
         match self {
             FileError::PermissionDenied { file, user } => (150, Status::Forbidden),
             FileError::InvalidDuration { string } => (151, Status::UnprocessableEntity),
+            FileError::Expired { key } => (152, Status::Gone),
+            FileError::PasswordRequired { key } => (153, Status::Unauthorized),
+            FileError::InvalidPassword { key } => (154, Status::Forbidden),
+            FileError::Throttled { key, seconds } => (155, Status::TooManyRequests),
+            FileError::Exhausted { key } => (156, Status::Gone),
+            FileError::InvalidMaxDownloads { string } => (157, Status::UnprocessableEntity),
+            FileError::NoThumbnail { key } => (158, Status::NotFound),
+        }
+    }
+}
+
+impl ErrorInfo for TusError {
+    #[allow(unused_variables)]
+    fn info(&self) -> (i32, Status) {
+        match self {
+            TusError::UnsupportedVersion { version } => (160, Status::PreconditionFailed),
+            TusError::MissingHeader { header } => (161, Status::BadRequest),
+            TusError::InvalidHeader { header, val } => (162, Status::BadRequest),
+            TusError::NotFound { id } => (163, Status::NotFound),
+            TusError::OffsetMismatch { expected, found } => (164, Status::Conflict),
+            TusError::TooLarge { length, limit } => (165, Status::PayloadTooLarge),
+            TusError::Locked { id } => (166, Status::Locked),
+        }
+    }
+}
+
+impl ErrorInfo for QuotaError {
+    #[allow(unused_variables)]
+    fn info(&self) -> (i32, Status) {
+        match self {
+            QuotaError::FileTooLarge { bytes, limit } => (170, Status::PayloadTooLarge),
+            QuotaError::BytesExceeded { used, bytes, limit } => (171, Status::InsufficientStorage),
+            QuotaError::FilesExceeded { files, limit } => (172, Status::InsufficientStorage),
+        }
+    }
+}
+
+impl ErrorInfo for TokenError {
+    #[allow(unused_variables)]
+    fn info(&self) -> (i32, Status) {
+        match self {
+            TokenError::NotFound { id } => (180, Status::NotFound),
+            TokenError::InvalidName {} => (181, Status::UnprocessableEntity),
+            TokenError::InvalidExpiry { string } => (182, Status::UnprocessableEntity),
         }
     }
 }
@@ -256,12 +379,34 @@ impl ErrorInfo for Error {
                 ClientError::AuthenticationError { error } => error.info(),
                 ClientError::MultipartError { error } => error.info(),
                 ClientError::FileError { error } => error.info(),
+                ClientError::TusError { error } => error.info(),
+                ClientError::QuotaError { error } => error.info(),
+                ClientError::TokenError { error } => error.info(),
             },
             Error::ServerError(_) => (100, Status::InternalServerError),
         }
     }
 }
 
+use rocket::{
+    response::{self, Responder, Response},
+    Request,
+};
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let (code, status) = self.info();
+        crate::util::metrics::get().error(code);
+        /* Display only, the debug output of a record may carry hashes. */
+        match self {
+            Error::ServerError(_) => error!("{} {}: {}", code, status, self),
+            Error::ClientError(_) => info!("{} {}: {}", code, status, self),
+        }
+        Response::build_from(json!({"error": status.reason, "code": code}).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
+
 use rocket::data::Outcome;
 pub fn failure<T, E>(error: E) -> Outcome<T, Error>
 where
@@ -0,0 +1,161 @@
+/// Longest stored name, in bytes; most filesystems allow no more.
+pub const MAX_LEN: usize = 255;
+/// Extensions up to this many bytes survive truncation.
+const MAX_EXTENSION_LEN: usize = 16;
+/// What a name that is empty after cleaning becomes.
+const FALLBACK: &'static str = "file";
+
+/// Directional overrides and isolates, which can make `exe.txt` look
+/// like `txt.exe`.
+fn is_bidi_control(c: char) -> bool {
+    match c {
+        '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' => true,
+        _ => false,
+    }
+}
+
+/// Clean an uploaded file name for storage and downloads. Only the last
+/// component of a path is kept, control characters are dropped, leading
+/// and trailing dots and spaces are trimmed and the length is capped.
+pub fn sanitize(name: &str) -> String {
+    let name = name.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+    let name: String = name
+        .chars()
+        .filter(|&c| !c.is_control() && !is_bidi_control(c))
+        .collect();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    match name.is_empty() {
+        true => String::from(FALLBACK),
+        false => truncate(name),
+    }
+}
+
+/// Cut a name down to `MAX_LEN` bytes on a character boundary, keeping a
+/// short extension.
+fn truncate(name: &str) -> String {
+    if name.len() <= MAX_LEN {
+        return name.to_string();
+    }
+    let extension = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_LEN => &name[dot..],
+        _ => "",
+    };
+    let mut end = MAX_LEN - extension.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &name[..end], extension)
+}
+
+/// A `Content-Disposition` value after RFC 6266: a plain ASCII `filename`
+/// for old clients and the exact name as RFC 5987 `filename*`.
+pub fn content_disposition(disposition: &str, name: &str) -> String {
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        ascii_fallback(name),
+        percent_encode(name)
+    )
+}
+
+/// The name with everything that isn't printable ASCII, and the quote and
+/// backslash a quoted string would need escaped, replaced by `_`.
+fn ascii_fallback(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// `attr-char` of RFC 5987 is kept, every other byte is percent encoded.
+fn percent_encode(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => out.push(byte as char),
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                out.push(byte as char)
+            }
+            byte => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_ordinary_names() {
+        assert_eq!(sanitize("holiday.jpg"), "holiday.jpg");
+        assert_eq!(sanitize("Übersicht 2020.pdf"), "Übersicht 2020.pdf");
+        assert_eq!(sanitize("🎉 party.png"), "🎉 party.png");
+    }
+
+    #[test]
+    fn strips_paths() {
+        assert_eq!(sanitize("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize("C:\\fakepath\\report.doc"), "report.doc");
+        assert_eq!(sanitize("dir/"), "file");
+        assert_eq!(sanitize(".."), "file");
+        assert_eq!(sanitize(""), "file");
+    }
+
+    #[test]
+    fn strips_control_characters() {
+        assert_eq!(sanitize("a\r\nSet-Cookie: x=1.txt"), "aSet-Cookie: x=1.txt");
+        assert_eq!(sanitize("nul\0byte\x7f.bin"), "nulbyte.bin");
+        assert_eq!(sanitize("invoice\u{202e}fdp.exe"), "invoicefdp.exe");
+        assert_eq!(sanitize("  .hidden.  "), "hidden");
+    }
+
+    #[test]
+    fn caps_length() {
+        let long = format!("{}.tar.gz", "a".repeat(300));
+        let name = sanitize(&long);
+        assert_eq!(name.len(), MAX_LEN);
+        assert!(name.ends_with(".gz"));
+
+        let wide = "ü".repeat(200);
+        let name = sanitize(&wide);
+        assert!(name.len() <= MAX_LEN);
+        assert!(name.chars().all(|c| c == 'ü'));
+
+        let extension = format!("name.{}", "x".repeat(300));
+        assert_eq!(sanitize(&extension).len(), MAX_LEN);
+    }
+
+    #[test]
+    fn disposition_of_plain_names() {
+        assert_eq!(
+            content_disposition("attachment", "report.pdf"),
+            "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+        );
+    }
+
+    #[test]
+    fn disposition_escapes_hostile_names() {
+        assert_eq!(
+            content_disposition("inline", "a\"; filename=evil.exe"),
+            "inline; filename=\"a_; filename=evil.exe\"; \
+             filename*=UTF-8''a%22%3B%20filename%3Devil.exe"
+        );
+        assert_eq!(
+            content_disposition("attachment", "back\\slash\r\n"),
+            "attachment; filename=\"back_slash__\"; filename*=UTF-8''back%5Cslash%0D%0A"
+        );
+    }
+
+    #[test]
+    fn disposition_of_unicode_names() {
+        assert_eq!(
+            content_disposition("attachment", "Grüße 🎉.txt"),
+            "attachment; filename=\"Gr__e _.txt\"; \
+             filename*=UTF-8''Gr%C3%BC%C3%9Fe%20%F0%9F%8E%89.txt"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::config::{LogConfig, LogFormat};
+use crate::util::random::random_ascii;
+use chrono::{SecondsFormat, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::RwLock;
+
+pub const REQUEST_ID_HEADER: &'static str = "X-Request-Id";
+const REQUEST_ID_LEN: usize = 16;
+const REQUEST_ID_MAX_LEN: usize = 64;
+/// Targets Rocket uses for formatting only, filtered by module instead.
+const ROCKET_TARGETS: &[&str] = &["_", "launch", "launch_"];
+
+// {{{ Filter
+
+/// Levels per module from directives like `info,flink::storage=debug`.
+/// The longest matching module wins, a bare level sets the default.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.find('=') {
+                Some(pos) => {
+                    let module = directive[..pos].trim();
+                    let level = directive[pos + 1..].trim();
+                    let level = level
+                        .parse()
+                        .map_err(|_| format!("unknown level '{}'", level))?;
+                    filter.modules.push((module.into(), level));
+                }
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    /* A bare module name logs everything it has to say. */
+                    Err(_) => filter.modules.push((directive.into(), LevelFilter::Trace)),
+                },
+            }
+        }
+        filter.modules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(filter)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module.as_str()
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+// }}}
+// {{{ Logger
+
+struct Settings {
+    filter: Filter,
+    format: LogFormat,
+}
+
+/// Writes log records to stderr, leaving stdout to command output.
+struct Logger {
+    settings: RwLock<Settings>,
+}
+
+lazy_static! {
+    static ref LOGGER: Logger = Logger {
+        settings: RwLock::new(Settings {
+            filter: Filter::parse(&LogConfig::default().filter).expect("[log] default filter"),
+            format: LogFormat::Text,
+        }),
+    };
+}
+
+thread_local! {
+    /// The request handled on this thread; Rocket runs each on one worker.
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Install the logger with the default filter, so loading the
+/// configuration can already log.
+pub fn init() {
+    if log::set_logger(&*LOGGER).is_ok() {
+        log::set_max_level(
+            LOGGER
+                .settings
+                .read()
+                .expect("[log] lock poisoned")
+                .filter
+                .max(),
+        );
+    }
+}
+
+/// Switch to the filter and format of `config`, taking effect immediately.
+pub fn configure(config: &LogConfig) -> Result<(), String> {
+    let filter = Filter::parse(&config.filter)?;
+    log::set_max_level(filter.max());
+    *LOGGER.settings.write().expect("[log] lock poisoned") = Settings {
+        filter: filter,
+        format: config.format,
+    };
+    Ok(())
+}
+
+/// The id of the request handled on this thread, if any.
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+fn set_request_id(value: Option<String>) {
+    REQUEST_ID.with(|id| *id.borrow_mut() = value);
+}
+
+impl Logger {
+    fn target<'a>(record: &Record<'a>) -> &'a str {
+        match record.module_path() {
+            Some(module) if ROCKET_TARGETS.contains(&record.target()) => module,
+            _ => record.target(),
+        }
+    }
+
+    fn format(format: LogFormat, target: &str, record: &Record) -> String {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let request = request_id();
+        match format {
+            LogFormat::Text => match request {
+                Some(request) => format!(
+                    "{} {:<5} {} [{}] {}",
+                    now,
+                    record.level(),
+                    target,
+                    request,
+                    record.args()
+                ),
+                None => format!("{} {:<5} {} {}", now, record.level(), target, record.args()),
+            },
+            LogFormat::Json => json!({
+                "time": now,
+                "level": record.level().to_string(),
+                "target": target,
+                "request_id": request,
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        /* Rocket's formatting targets are checked again in `log`. */
+        ROCKET_TARGETS.contains(&metadata.target())
+            || match self.settings.read() {
+                Ok(settings) => metadata.level() <= settings.filter.level(metadata.target()),
+                Err(_) => true,
+            }
+    }
+
+    fn log(&self, record: &Record) {
+        let settings = match self.settings.read() {
+            Ok(settings) => settings,
+            Err(_) => return,
+        };
+        let target = Self::target(record);
+        if record.level() > settings.filter.level(target) {
+            return;
+        }
+        let line = Self::format(settings.format, target, record);
+        let _ = writeln!(std::io::stderr(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+// }}}
+// {{{ Request IDs
+
+/// The id every log line of a request is tagged with.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Tags each request with an id, taken from a sane `X-Request-Id` header
+/// (e.g. set by the proxy) or generated, and echoes it in the response.
+pub struct RequestIds;
+
+impl RequestIds {
+    fn valid(id: &&str) -> bool {
+        !id.is_empty()
+            && id.len() <= REQUEST_ID_MAX_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    }
+}
+
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request IDs",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .filter(Self::valid)
+            .map(String::from)
+            .unwrap_or_else(|| random_ascii(REQUEST_ID_LEN));
+        set_request_id(Some(id.clone()));
+        request.local_cache(|| RequestId(id));
+        debug!("{} {}", request.method(), request.uri());
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let id = request.local_cache(|| RequestId(random_ascii(REQUEST_ID_LEN)));
+        info!(
+            "{} {} -> {}",
+            request.method(),
+            request.uri(),
+            response.status()
+        );
+        response.set_raw_header(REQUEST_ID_HEADER, id.0.clone());
+        set_request_id(None);
+    }
+}
+
+// }}}
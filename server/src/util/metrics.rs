@@ -0,0 +1,321 @@
+use crate::db::connection::SqlitePool;
+use crate::model::UserUsage;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::{self, Read};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+use std::time::Instant;
+
+/// Upper bounds of the request latency buckets, in seconds.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+lazy_static! {
+    static ref METRICS: Metrics = Metrics::default();
+}
+
+/// The process wide metrics, rendered by the `/metrics` endpoint.
+pub fn get() -> &'static Metrics {
+    &*METRICS
+}
+
+// {{{ Metrics
+
+#[derive(Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// `(method, route, status)` of a request.
+type RequestLabels = (String, String, u16);
+
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<RequestLabels, Histogram>>,
+    errors: Mutex<BTreeMap<i32, u64>>,
+    usage: Mutex<Vec<UserUsage>>,
+    uploads: AtomicU64,
+    upload_bytes: AtomicU64,
+    downloads: AtomicU64,
+    download_bytes: AtomicU64,
+}
+
+impl Metrics {
+    pub fn request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests
+                .entry((method.into(), route.into(), status))
+                .or_default()
+                .observe(seconds);
+        }
+    }
+
+    /// An error response with one of the codes of `util::error`.
+    pub fn error(&self, code: i32) {
+        if let Ok(mut errors) = self.errors.lock() {
+            *errors.entry(code).or_insert(0) += 1;
+        }
+    }
+
+    pub fn upload(&self, bytes: u64) {
+        self.uploads.fetch_add(1, Ordering::Relaxed);
+        self.upload_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn download(&self) {
+        self.downloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the bytes of a download as they are sent.
+    pub fn count_download(&'static self, body: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        Box::new(Counted {
+            inner: body,
+            counter: &self.download_bytes,
+        })
+    }
+
+    /// Replace the stored files and bytes per user.
+    pub fn set_usage(&self, usage: Vec<UserUsage>) {
+        if let Ok(mut current) = self.usage.lock() {
+            *current = usage;
+        }
+    }
+
+    /// Everything in the Prometheus text format.
+    pub fn render(&self, pool: &SqlitePool) -> String {
+        let mut out = String::new();
+        /* Writing to a String can't fail. */
+        let _ = self.render_requests(&mut out);
+        let _ = self.render_errors(&mut out);
+        let _ = self.render_transfers(&mut out);
+        let _ = self.render_usage(&mut out);
+        let _ = Self::render_pool(&mut out, pool);
+        out
+    }
+
+    fn render_requests(&self, out: &mut String) -> std::fmt::Result {
+        let requests = match self.requests.lock() {
+            Ok(requests) => requests,
+            Err(_) => return Ok(()),
+        };
+        writeln!(
+            out,
+            "# HELP flink_http_request_duration_seconds Time spent answering requests."
+        )?;
+        writeln!(out, "# TYPE flink_http_request_duration_seconds histogram")?;
+        for ((method, route, status), histogram) in requests.iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{}\"",
+                method,
+                escape(route),
+                status
+            );
+            for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                writeln!(
+                    out,
+                    "flink_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                )?;
+            }
+            writeln!(
+                out,
+                "flink_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            )?;
+            writeln!(
+                out,
+                "flink_http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            )?;
+            writeln!(
+                out,
+                "flink_http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            )?;
+        }
+        Ok(())
+    }
+
+    fn render_errors(&self, out: &mut String) -> std::fmt::Result {
+        let errors = match self.errors.lock() {
+            Ok(errors) => errors,
+            Err(_) => return Ok(()),
+        };
+        writeln!(
+            out,
+            "# HELP flink_errors_total Error responses by error code."
+        )?;
+        writeln!(out, "# TYPE flink_errors_total counter")?;
+        for (code, count) in errors.iter() {
+            writeln!(out, "flink_errors_total{{code=\"{}\"}} {}", code, count)?;
+        }
+        Ok(())
+    }
+
+    fn render_transfers(&self, out: &mut String) -> std::fmt::Result {
+        let counters = [
+            ("flink_uploads_total", "Files uploaded.", &self.uploads),
+            (
+                "flink_upload_bytes_total",
+                "Bytes of uploaded files.",
+                &self.upload_bytes,
+            ),
+            (
+                "flink_downloads_total",
+                "Files downloaded.",
+                &self.downloads,
+            ),
+            (
+                "flink_download_bytes_total",
+                "Bytes sent to downloads.",
+                &self.download_bytes,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            writeln!(out, "# HELP {} {}", name, help)?;
+            writeln!(out, "# TYPE {} counter", name)?;
+            writeln!(out, "{} {}", name, value.load(Ordering::Relaxed))?;
+        }
+        Ok(())
+    }
+
+    fn render_usage(&self, out: &mut String) -> std::fmt::Result {
+        let usage = match self.usage.lock() {
+            Ok(usage) => usage,
+            Err(_) => return Ok(()),
+        };
+        writeln!(out, "# HELP flink_user_files Files stored per user.")?;
+        writeln!(out, "# TYPE flink_user_files gauge")?;
+        for user in usage.iter() {
+            writeln!(
+                out,
+                "flink_user_files{{user=\"{}\"}} {}",
+                escape(&user.name),
+                user.files
+            )?;
+        }
+        writeln!(out, "# HELP flink_user_bytes Bytes stored per user.")?;
+        writeln!(out, "# TYPE flink_user_bytes gauge")?;
+        for user in usage.iter() {
+            writeln!(
+                out,
+                "flink_user_bytes{{user=\"{}\"}} {}",
+                escape(&user.name),
+                user.bytes
+            )?;
+        }
+        Ok(())
+    }
+
+    fn render_pool(out: &mut String, pool: &SqlitePool) -> std::fmt::Result {
+        let state = pool.state();
+        let gauges = [
+            (
+                "flink_db_pool_connections",
+                "Open database connections.",
+                state.connections,
+            ),
+            (
+                "flink_db_pool_idle_connections",
+                "Idle database connections.",
+                state.idle_connections,
+            ),
+            (
+                "flink_db_pool_max_connections",
+                "Size of the connection pool.",
+                pool.max_size(),
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            writeln!(out, "# HELP {} {}", name, help)?;
+            writeln!(out, "# TYPE {} gauge", name)?;
+            writeln!(out, "{} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Label values are quoted, so backslashes, quotes and newlines are escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct Counted {
+    inner: Box<dyn Read + Send>,
+    counter: &'static AtomicU64,
+}
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.counter.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+// }}}
+// {{{ Fairing
+
+struct RequestStart(Instant);
+
+/// Times every request, labelled by the route that answered it.
+pub struct RequestMetrics;
+
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let elapsed = start.0.elapsed();
+        /* Route patterns, not paths, so file keys don't become labels. */
+        let route = match request.route() {
+            Some(route) => route.uri.path().to_string(),
+            None => String::from("unmatched"),
+        };
+        get().request(
+            request.method().as_str(),
+            &route,
+            response.status().code,
+            elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9,
+        );
+    }
+}
+
+// }}}
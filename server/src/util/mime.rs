@@ -0,0 +1,101 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// What is read of an upload to recognize it.
+const SNIFF_LEN: u64 = 512;
+pub const OCTET_STREAM: &'static str = "application/octet-stream";
+pub const PLAIN_TEXT: &'static str = "text/plain; charset=utf-8";
+
+/// Magic bytes, each at an offset, that all have to be present.
+const SIGNATURES: &[(&[(usize, &[u8])], &'static str)] = &[
+    (&[(0, b"\x89PNG\r\n\x1a\n")], "image/png"),
+    (&[(0, b"\xff\xd8\xff")], "image/jpeg"),
+    (&[(0, b"GIF8")], "image/gif"),
+    (&[(0, b"RIFF"), (8, b"WEBP")], "image/webp"),
+    (&[(0, b"RIFF"), (8, b"WAVE")], "audio/wav"),
+    /* The reserved fields after the size keep text starting with BM out. */
+    (&[(0, b"BM"), (6, b"\0\0\0\0")], "image/bmp"),
+    (&[(0, b"II*\0")], "image/tiff"),
+    (&[(0, b"MM\0*")], "image/tiff"),
+    (&[(0, b"%PDF-")], "application/pdf"),
+    (&[(0, b"PK\x03\x04")], "application/zip"),
+    (&[(0, b"\x1f\x8b")], "application/gzip"),
+    (&[(0, b"7z\xbc\xaf\x27\x1c")], "application/x-7z-compressed"),
+    (&[(0, b"ID3")], "audio/mpeg"),
+    (&[(0, b"fLaC")], "audio/flac"),
+    (&[(0, b"OggS")], "audio/ogg"),
+    (&[(0, b"\x1a\x45\xdf\xa3")], "video/webm"),
+    (&[(4, b"ftyp")], "video/mp4"),
+];
+
+/// Markup that browsers run scripts in, recognized by its first tag.
+const MARKUP: &[(&'static str, &'static str)] = &[
+    ("<!doctype html", "text/html"),
+    ("<html", "text/html"),
+    ("<head", "text/html"),
+    ("<body", "text/html"),
+    ("<script", "text/html"),
+    ("<svg", "image/svg+xml"),
+];
+
+/// The MIME type of an upload, judged by its first bytes. The name the
+/// uploader gave it isn't trusted.
+pub fn sniff(path: &Path) -> Option<&'static str> {
+    let mut data = Vec::new();
+    let read = fs::File::open(path).and_then(|file| file.take(SNIFF_LEN).read_to_end(&mut data));
+    if let Err(error) = read {
+        debug!("cannot sniff {}: {}", path.display(), error);
+        return None;
+    }
+    detect(&data)
+}
+
+pub fn detect(data: &[u8]) -> Option<&'static str> {
+    for (signature, mime) in SIGNATURES {
+        if signature
+            .iter()
+            .all(|(offset, magic)| data.get(*offset..offset + magic.len()) == Some(*magic))
+        {
+            return Some(*mime);
+        }
+    }
+    if !is_text(data) {
+        return None;
+    }
+    /* SVG usually starts with an XML declaration or a comment, so look
+     * further than the first bytes for markup. */
+    let text = String::from_utf8_lossy(data).to_lowercase();
+    for (tag, mime) in MARKUP {
+        if text.trim_start().starts_with(tag) || (*mime == "image/svg+xml" && text.contains(tag)) {
+            return Some(*mime);
+        }
+    }
+    Some(PLAIN_TEXT)
+}
+
+/// UTF-8 without control characters besides whitespace. A multi-byte
+/// character may be cut off at the end of the sniffed bytes.
+fn is_text(data: &[u8]) -> bool {
+    if data.is_empty() {
+        return false;
+    }
+    let valid = match std::str::from_utf8(data) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none(),
+    };
+    valid
+        && data.iter().all(|&byte| {
+            byte >= 0x20 || byte == b'\t' || byte == b'\n' || byte == b'\r' || byte == 0x0c
+        })
+}
+
+/// Types browsers display without running anything the uploader wrote.
+/// Everything else, HTML and SVG in particular, is only downloaded.
+pub fn is_inline_safe(mime: &str) -> bool {
+    match mime {
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp"
+        | "application/pdf" | PLAIN_TEXT => true,
+        mime => mime.starts_with("audio/") || mime.starts_with("video/"),
+    }
+}
@@ -2,7 +2,17 @@ pub mod arg;
 pub mod date;
 pub mod download;
 pub mod error;
+pub mod filename;
+pub mod logging;
+pub mod metrics;
+pub mod mime;
 pub mod multipart;
 pub mod random;
+pub mod secret;
+pub mod size;
 pub mod sql;
+pub mod throttle;
+pub mod thumbnail;
+pub mod tus;
+pub mod unlock;
 pub mod upload;
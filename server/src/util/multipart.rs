@@ -1,3 +1,4 @@
+use crate::config;
 use crate::util::error::{Error as E, Res};
 
 use multipart::server::save::SaveResult::*;
@@ -10,8 +11,6 @@ use rocket::{
 };
 use std::path::{Path, PathBuf};
 
-pub const SIZE_LIMIT: u64 = 5368709120;
-
 pub struct MultipartForm {
     pub entries: Entries,
     pub partial: Option<String>,
@@ -34,7 +33,7 @@ impl MultipartForm {
             Outcome::Success(value) => value,
             _ => {
                 let message = "content-type not set";
-                println!("[multipart] error: {}", message);
+                warn!("error: {}", message);
                 return Err(MultipartError::RequestError {
                     message: message.into(),
                 }
@@ -48,7 +47,7 @@ impl MultipartForm {
         {
             Ok((_, boundary)) => Ok(boundary),
             Err(message) => {
-                println!("[multipart] error: {}", message);
+                warn!("error: {}", message);
                 Err(MultipartError::RequestError { message }.into())
             }
         }
@@ -57,11 +56,11 @@ impl MultipartForm {
     pub fn from_bounded_data(data: Data, boundary: &str, path: &Path) -> Res<Self> {
         match Multipart::with_body(data.open(), boundary)
             .save()
-            .size_limit(SIZE_LIMIT)
+            .size_limit(config::get().size_limit)
             .with_dir(path)
         {
             Full(entries) => {
-                println!("[multipart] read full form");
+                debug!("read full form");
                 let form = Self {
                     entries: entries,
                     partial: None,
@@ -70,17 +69,17 @@ impl MultipartForm {
                 Ok(form)
             }
             Partial(partial, reason) => {
-                println!("[multipart] read partial form");
+                debug!("read partial form");
                 let partial_name = match partial.partial {
                     Some(field) => {
                         let name = format!("{:?}", field.source.headers);
-                        println!("[multipart] name: {}", name);
+                        debug!("name: {}", name);
                         Some(name)
                     }
                     _ => None,
                 };
                 let reason = format!("{:?}", reason);
-                println!("[multipart] reason: {}", reason);
+                debug!("reason: {}", reason);
                 let form = Self {
                     entries: partial.entries,
                     partial: partial_name,
@@ -89,7 +88,7 @@ impl MultipartForm {
                 Ok(form)
             }
             Error(e) => {
-                println!("[multipart] error: {:?}", e);
+                warn!("error: {:?}", e);
                 return Err(e.into());
             }
         }
@@ -130,6 +129,17 @@ impl MultipartForm {
             }),
         }
     }
+    pub fn get_text_opt<'a>(&'a self, key: &str) -> Result<Option<String>, MultipartError> {
+        match self.get_opt(key) {
+            Some(SavedData::Text(val)) => Ok(Some(val.clone())),
+            Some(_) => Err(MultipartError::ValueError {
+                key: key.into(),
+                val: String::from("Text"),
+            }),
+            None => Ok(None),
+        }
+    }
+
     pub fn get_file<'a>(&'a self, key: &str) -> Result<(PathBuf, usize), MultipartError> {
         match self.get(key)? {
             SavedData::File(val, len) => Ok((val.clone(), *len as usize)),
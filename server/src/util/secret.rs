@@ -0,0 +1,81 @@
+use diesel::{
+    backend::Backend,
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+    sqlite::Sqlite,
+};
+use rocket::{http::RawStr, request::FromFormValue};
+use std::fmt;
+use std::io::Write;
+
+const REDACTED: &'static str = "[redacted]";
+
+/// A password or token. It can be read from forms, JSON and the database,
+/// but never shows up in `Display` or `Debug` output, so it cannot end up
+/// in logs or error messages by accident. `expose` hands out the contents
+/// where they are really needed.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(transparent)]
+#[sql_type = "Text"]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Secret(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Secret(secret)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl<'v> FromFormValue<'v> for Secret {
+    type Error = ();
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, ()> {
+        match value.url_decode() {
+            Ok(value) => Ok(Secret(value)),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for Secret {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        <String as ToSql<Text, Sqlite>>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Secret {
+    fn from_sql(bytes: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        <String as FromSql<Text, Sqlite>>::from_sql(bytes).map(Secret)
+    }
+}
+
+/// Helper for `Debug` impls of records with secret fields.
+pub fn redacted<T>(value: &Option<T>) -> Option<&'static str> {
+    value.as_ref().map(|_| REDACTED)
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counts failed attempts per identifier and blocks it for the rest of
+/// the window once too many have piled up.
+pub struct Throttle {
+    attempts: Mutex<HashMap<String, Attempts>>,
+    max_attempts: u32,
+    window: Duration,
+}
+
+struct Attempts {
+    count: u32,
+    first: Instant,
+}
+
+impl Throttle {
+    pub fn new(max_attempts: u32, window: Duration) -> Self {
+        Self {
+            attempts: Mutex::new(HashMap::new()),
+            max_attempts,
+            window,
+        }
+    }
+
+    /// Time left until `id` may try again, if it is blocked.
+    pub fn blocked(&self, id: &str) -> Option<Duration> {
+        let attempts = self.attempts.lock().expect("[throttle] lock poisoned");
+        match attempts.get(id) {
+            Some(a) if a.count >= self.max_attempts && a.first.elapsed() < self.window => {
+                Some(self.window - a.first.elapsed())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn fail(&self, id: &str) {
+        let mut attempts = self.attempts.lock().expect("[throttle] lock poisoned");
+        let window = self.window;
+        attempts.retain(|_, a| a.first.elapsed() < window);
+        attempts
+            .entry(id.into())
+            .or_insert_with(|| Attempts {
+                count: 0,
+                first: Instant::now(),
+            })
+            .count += 1;
+    }
+
+    pub fn reset(&self, id: &str) {
+        let mut attempts = self.attempts.lock().expect("[throttle] lock poisoned");
+        attempts.remove(id);
+    }
+}
+
+/// Makes `id` wait twice as long after every failure, starting at a second,
+/// and locks it out once `max_failures` have piled up. Failures are
+/// forgotten after a quiet `lockout` period.
+pub struct Backoff {
+    failures: Mutex<HashMap<String, Failures>>,
+    max_failures: u32,
+    lockout: Duration,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    until: Instant,
+}
+
+impl Backoff {
+    pub fn new(max_failures: u32, lockout: Duration) -> Self {
+        Self {
+            failures: Mutex::new(HashMap::new()),
+            max_failures,
+            lockout,
+        }
+    }
+
+    /// Time left until `id` may try again, if it has to wait.
+    pub fn blocked(&self, id: &str) -> Option<Duration> {
+        let failures = self.failures.lock().expect("[throttle] lock poisoned");
+        let now = Instant::now();
+        match failures.get(id) {
+            Some(f) if f.until > now => Some(f.until - now),
+            _ => None,
+        }
+    }
+
+    /// Count a failure, returns the number of failures if it locked `id` out.
+    pub fn fail(&self, id: &str) -> Option<u32> {
+        let mut failures = self.failures.lock().expect("[throttle] lock poisoned");
+        let now = Instant::now();
+        let lockout = self.lockout;
+        failures.retain(|_, f| f.until > now || f.last.elapsed() < lockout);
+
+        let f = failures.entry(id.into()).or_insert_with(|| Failures {
+            count: 0,
+            last: now,
+            until: now,
+        });
+        f.count += 1;
+        f.last = now;
+        if f.count >= self.max_failures {
+            /* Attempts are refused while locked out, so this starts a new lockout. */
+            f.until = now + lockout;
+            Some(f.count)
+        } else {
+            let wait = Duration::from_secs(1 << (f.count - 1).min(16));
+            f.until = now + wait.min(lockout);
+            None
+        }
+    }
+
+    pub fn reset(&self, id: &str) {
+        let mut failures = self.failures.lock().expect("[throttle] lock poisoned");
+        failures.remove(id);
+    }
+}
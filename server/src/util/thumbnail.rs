@@ -0,0 +1,130 @@
+use crate::config;
+use crate::storage::Storage;
+use crate::util::error::Res;
+
+use image::{FilterType, GenericImageView, ImageDecoder, ImageFormat, ImageOutputFormat};
+use std::fs;
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+const THUMB_DIR: &'static str = "thumb";
+/// Enough of a blob to recognize an image by its magic bytes.
+const SNIFF_LEN: u64 = 64;
+const JPEG_QUALITY: u8 = 80;
+
+/// Thumbnails are a cache kept below `dir`, whatever the storage backend.
+pub fn thumb_dir() -> PathBuf {
+    config::get().dir.join(THUMB_DIR)
+}
+
+/// File keys are unique, so they name the thumbnail on their own.
+pub fn path(key: &str) -> PathBuf {
+    thumb_dir().join(format!("{}.jpg", key))
+}
+
+fn is_supported(format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::PNG
+        | ImageFormat::JPEG
+        | ImageFormat::GIF
+        | ImageFormat::BMP
+        | ImageFormat::TIFF => true,
+        _ => false,
+    }
+}
+
+/// Width and height from the image header, without decoding any pixels.
+fn dimensions(format: ImageFormat, data: &[u8]) -> Option<(u64, u64)> {
+    let data = io::Cursor::new(data);
+    let found = match format {
+        ImageFormat::PNG => image::png::PNGDecoder::new(data).map(|d| d.dimensions()),
+        ImageFormat::JPEG => image::jpeg::JPEGDecoder::new(data).map(|d| d.dimensions()),
+        ImageFormat::GIF => image::gif::Decoder::new(data).map(|d| d.dimensions()),
+        ImageFormat::BMP => image::bmp::BMPDecoder::new(data).map(|d| d.dimensions()),
+        ImageFormat::TIFF => image::tiff::TIFFDecoder::new(data).map(|d| d.dimensions()),
+        _ => return None,
+    };
+    found.ok()
+}
+
+/// Render the thumbnail of a stored blob. Returns `false` for files that
+/// aren't images we can decode, or that are too large to try. A decoder
+/// panicking on a hostile image counts as not decodable.
+pub fn generate(user_id: i32, key: &str, storage: &dyn Storage) -> Res<bool> {
+    match panic::catch_unwind(AssertUnwindSafe(|| render(user_id, key, storage))) {
+        Ok(rendered) => rendered,
+        Err(_) => {
+            warn!("thumbnail decoder panicked on {}", key);
+            Ok(false)
+        }
+    }
+}
+
+fn render(user_id: i32, key: &str, storage: &dyn Storage) -> Res<bool> {
+    let config = &config::get().thumbnails;
+    let mut body = storage.get(user_id, key)?;
+
+    let mut data = Vec::new();
+    (&mut body).take(SNIFF_LEN).read_to_end(&mut data)?;
+    let format = match image::guess_format(&data) {
+        Ok(format) if is_supported(format) => format,
+        _ => return Ok(false),
+    };
+    (&mut body)
+        .take(config.max_bytes + 1)
+        .read_to_end(&mut data)?;
+    if data.len() as u64 > config.max_bytes {
+        return Ok(false);
+    }
+    /* A few compressed bytes can claim gigapixels, so the header is
+     * checked before anything is allocated for the pixels. */
+    match dimensions(format, &data) {
+        Some((width, height)) if width.saturating_mul(height) <= config.max_pixels => {}
+        Some((width, height)) => {
+            debug!("not decoding {}: {}x{} pixels", key, width, height);
+            return Ok(false);
+        }
+        None => {
+            debug!("cannot read the dimensions of {}", key);
+            return Ok(false);
+        }
+    }
+
+    let image = match image::load_from_memory(&data) {
+        Ok(image) => image,
+        Err(error) => {
+            debug!("cannot decode {}: {}", key, error);
+            return Ok(false);
+        }
+    };
+    /* Small images are kept as they are, only re-encoded. */
+    let image = match image.width() > config.size || image.height() > config.size {
+        true => image.resize(config.size, config.size, FilterType::Triangle),
+        false => image,
+    };
+    /* JPEG has no alpha channel. */
+    let thumb = image::DynamicImage::ImageRgb8(image.to_rgb());
+    let mut out = Vec::new();
+    if let Err(error) = thumb.write_to(&mut out, ImageOutputFormat::JPEG(JPEG_QUALITY)) {
+        warn!("cannot encode thumbnail of {}: {}", key, error);
+        return Ok(false);
+    }
+
+    /* Written aside and renamed, so a half written thumbnail is never served. */
+    fs::create_dir_all(thumb_dir())?;
+    let target = path(key);
+    let partial = target.with_extension("part");
+    fs::write(&partial, &out)?;
+    fs::rename(&partial, &target)?;
+    Ok(true)
+}
+
+/// Drop the thumbnail of a file, if it has one.
+pub fn remove(key: &str) {
+    match fs::remove_file(path(key)) {
+        Ok(()) => {}
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => warn!("failed to remove thumbnail of {}: {}", key, error),
+    }
+}
@@ -0,0 +1,269 @@
+use crate::api::app::files::{user_spool, UploadInfo};
+use crate::util::error::Res;
+
+use fs2::FileExt;
+use rocket::{
+    http::Status,
+    request::{self, FromRequest},
+    response::{self, Responder, Response},
+    Outcome, Request,
+};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+
+pub const TUS_VERSION: &'static str = "1.0.0";
+pub const TUS_EXTENSIONS: &'static str = "creation,termination";
+pub const TUS_CONTENT_TYPE: &'static str = "application/offset+octet-stream";
+
+#[derive(Debug, Fail)]
+pub enum TusError {
+    #[fail(display = "unsupported protocol version: {:?}", version)]
+    UnsupportedVersion { version: Option<String> },
+    #[fail(display = "missing header: {}", header)]
+    MissingHeader { header: String },
+    #[fail(display = "invalid header: {} -> {}", header, val)]
+    InvalidHeader { header: String, val: String },
+    #[fail(display = "upload not found: {}", id)]
+    NotFound { id: String },
+    #[fail(display = "offset mismatch: expected {}, got {}", expected, found)]
+    OffsetMismatch { expected: u64, found: u64 },
+    #[fail(display = "upload too large: {} > {}", length, limit)]
+    TooLarge { length: u64, limit: u64 },
+    #[fail(display = "upload busy: {}", id)]
+    Locked { id: String },
+}
+
+/// The tus headers of a request, validated lazily by the handlers so that
+/// failures are reported through the usual error responses.
+pub struct TusHeaders {
+    pub version: Option<String>,
+    pub length: Option<String>,
+    pub offset: Option<String>,
+    pub metadata: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl TusHeaders {
+    pub fn check_version(&self) -> Res<()> {
+        match self.version.as_ref().map(String::as_str) {
+            Some(TUS_VERSION) => Ok(()),
+            _ => Err(TusError::UnsupportedVersion {
+                version: self.version.clone(),
+            }
+            .into()),
+        }
+    }
+
+    pub fn check_content_type(&self) -> Res<()> {
+        match self.content_type.as_ref().map(String::as_str) {
+            Some(TUS_CONTENT_TYPE) => Ok(()),
+            Some(val) => Err(TusError::InvalidHeader {
+                header: String::from("Content-Type"),
+                val: val.into(),
+            }
+            .into()),
+            None => Err(Self::missing("Content-Type")),
+        }
+    }
+
+    pub fn length(&self) -> Res<u64> {
+        Self::number("Upload-Length", &self.length)
+    }
+
+    pub fn offset(&self) -> Res<u64> {
+        Self::number("Upload-Offset", &self.offset)
+    }
+
+    /// Decode `Upload-Metadata`, a list of `key base64(value)` pairs.
+    pub fn metadata_opt(&self, key: &str) -> Res<Option<String>> {
+        let metadata = match self.metadata {
+            Some(ref metadata) => metadata,
+            None => return Ok(None),
+        };
+        for pair in metadata.split(',') {
+            let mut parts = pair.trim().splitn(2, ' ');
+            if parts.next() != Some(key) {
+                continue;
+            }
+            let value = base64::decode(parts.next().unwrap_or("").trim())?;
+            return Ok(Some(String::from_utf8(value)?));
+        }
+        Ok(None)
+    }
+
+    pub fn metadata(&self, key: &str) -> Res<String> {
+        match self.metadata_opt(key)? {
+            Some(value) => Ok(value),
+            None => Err(Self::missing(&format!("Upload-Metadata: {}", key))),
+        }
+    }
+
+    fn number(header: &str, value: &Option<String>) -> Res<u64> {
+        match value {
+            Some(val) => val.parse().map_err(|_| {
+                TusError::InvalidHeader {
+                    header: header.into(),
+                    val: val.clone(),
+                }
+                .into()
+            }),
+            None => Err(Self::missing(header)),
+        }
+    }
+
+    fn missing(header: &str) -> crate::util::error::Error {
+        TusError::MissingHeader {
+            header: header.into(),
+        }
+        .into()
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for TusHeaders {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let headers = request.headers();
+        let get = |name: &str| headers.get_one(name).map(String::from);
+        Outcome::Success(TusHeaders {
+            version: get("Tus-Resumable"),
+            length: get("Upload-Length"),
+            offset: get("Upload-Offset"),
+            metadata: get("Upload-Metadata"),
+            content_type: get("Content-Type"),
+        })
+    }
+}
+
+/// A tus response, always carrying the `Tus-Resumable` header.
+pub struct TusResponse {
+    pub status: Status,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl TusResponse {
+    pub fn new(status: Status) -> Self {
+        TusResponse {
+            status: status,
+            headers: vec![("Tus-Resumable", TUS_VERSION.into())],
+        }
+    }
+
+    pub fn header<V: ToString>(mut self, name: &'static str, value: V) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+}
+
+impl<'r> Responder<'r> for TusResponse {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        let mut response = Response::build();
+        response.status(self.status);
+        for (name, value) in self.headers {
+            response.raw_header(name, value);
+        }
+        response.ok()
+    }
+}
+
+/// An unfinished upload, kept as `<id>.part` next to its `<id>.info`
+/// in the owner's spool directory.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TusUpload {
+    pub id: String,
+    pub user_id: i32,
+    pub length: u64,
+    pub info: UploadInfo,
+}
+
+impl TusUpload {
+    pub fn part_path(user_id: i32, id: &str) -> PathBuf {
+        user_spool(user_id).join(format!("{}.part", id))
+    }
+
+    pub fn info_path(user_id: i32, id: &str) -> PathBuf {
+        user_spool(user_id).join(format!("{}.info", id))
+    }
+
+    pub fn create(&self) -> Res<()> {
+        fs::File::create(Self::part_path(self.user_id, &self.id))?;
+        fs::write(
+            Self::info_path(self.user_id, &self.id),
+            serde_json::to_vec(self)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn load(user_id: i32, id: &str) -> Res<Self> {
+        /* Ids are generated alphanumeric, anything else can't be ours. */
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(TusError::NotFound { id: id.into() }.into());
+        }
+        match fs::read(Self::info_path(user_id, id)) {
+            Ok(info) => Ok(serde_json::from_slice(&info)?),
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {
+                Err(TusError::NotFound { id: id.into() }.into())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn offset(&self) -> Res<u64> {
+        Ok(fs::metadata(self.part())?.len())
+    }
+
+    /// Open the part file for appending and lock it, so a concurrent
+    /// append to the same upload is turned away until this one is done.
+    /// The lock is released when the file is dropped.
+    pub fn lock(&self) -> Res<fs::File> {
+        let not_found = || TusError::NotFound {
+            id: self.id.clone(),
+        };
+        let part = match OpenOptions::new().append(true).open(self.part()) {
+            Ok(part) => part,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                return Err(not_found().into())
+            }
+            Err(error) => return Err(error.into()),
+        };
+        match part.try_lock_exclusive() {
+            Ok(()) => {}
+            Err(ref error) if error.kind() == fs2::lock_contended_error().kind() => {
+                return Err(TusError::Locked {
+                    id: self.id.clone(),
+                }
+                .into())
+            }
+            Err(error) => return Err(error.into()),
+        }
+        /* The previous holder may have finished the upload and moved the
+         * part file into storage, it is only ours while the info exists. */
+        if !Self::info_path(self.user_id, &self.id).exists() {
+            return Err(not_found().into());
+        }
+        Ok(part)
+    }
+
+    pub fn part(&self) -> PathBuf {
+        Self::part_path(self.user_id, &self.id)
+    }
+
+    /// Forget the upload, leaving the part file to whoever consumed it.
+    pub fn finish(&self) -> Res<()> {
+        fs::remove_file(Self::info_path(self.user_id, &self.id))?;
+        Ok(())
+    }
+
+    pub fn remove(&self) -> Res<()> {
+        for path in &[self.part(), Self::info_path(self.user_id, &self.id)] {
+            match fs::remove_file(path) {
+                Err(ref error) if error.kind() != std::io::ErrorKind::NotFound => {
+                    warn!("failed to remove {:?}: {:?}", path, error);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
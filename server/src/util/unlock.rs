@@ -0,0 +1,68 @@
+use rocket::{
+    http::{ContentType, Status},
+    request::{self, FromRequest},
+    response::{self, Responder, Response},
+    Outcome, Request,
+};
+use std::io::Cursor;
+
+use crate::util::secret::Secret;
+
+/// The form asking for a share link's password.
+pub struct UnlockForm {
+    pub key: String,
+    pub failed: bool,
+    /// Posts to `/view`, so the file is shown once unlocked.
+    pub view: bool,
+}
+
+impl UnlockForm {
+    fn render(&self) -> String {
+        let message = match self.failed {
+            true => "<p>Wrong password, please try again.</p>",
+            false => "<p>This file is protected by a password.</p>",
+        };
+        let (action, button) = match self.view {
+            true => ("/view", "View"),
+            false => ("", "Download"),
+        };
+        format!(
+            "<!DOCTYPE html>\n\
+             <html><head><meta charset=\"utf-8\"><title>[f]link</title></head><body>\n\
+             {}\n\
+             <form method=\"post\" action=\"/f/{}{}\">\n\
+             <input type=\"password\" name=\"password\" autofocus>\n\
+             <button type=\"submit\">{}</button>\n\
+             </form>\n\
+             </body></html>\n",
+            message, self.key, action, button
+        )
+    }
+}
+
+impl Responder<'_> for UnlockForm {
+    fn respond_to(self, _req: &Request<'_>) -> response::Result<'static> {
+        let status = match self.failed {
+            true => Status::Forbidden,
+            false => Status::Unauthorized,
+        };
+        Response::build()
+            .status(status)
+            .header(ContentType::HTML)
+            .raw_header("Cache-Control", "no-store")
+            .sized_body(Cursor::new(self.render()))
+            .ok()
+    }
+}
+
+/// A share link password sent by scripts in the `X-Flink-Password` header.
+pub struct SharePassword(pub Option<Secret>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for SharePassword {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let password = request.headers().get_one("X-Flink-Password");
+        Outcome::Success(SharePassword(password.map(Secret::new)))
+    }
+}
@@ -1,6 +1,8 @@
-use crate::model::User;
+use crate::db;
+use crate::model::{Quota, User};
 use crate::util::error::{failure, Error as ApiError};
 use crate::util::multipart::MultipartForm;
+use crate::util::secret::Secret;
 
 use rocket::{
     data::{self, FromDataSimple},
@@ -14,6 +16,9 @@ pub struct FileUpload {
     pub form: MultipartForm,
     pub name: String,
     pub meta: String,
+    pub password: Option<Secret>,
+    pub max_downloads: Option<String>,
+    pub burn: Option<String>,
     pub file: PathBuf,
     pub size: usize,
 }
@@ -33,6 +38,24 @@ impl FromDataSimple for FileUpload {
             }
         };
 
+        /* The size is only known once the data is read, but a user who is
+         * already at their limit can be turned away right now. The
+         * connection goes back to the pool before the body is read, which
+         * may take long. */
+        {
+            let connection = match request.guard::<db::Connection>() {
+                Outcome::Success(value) => value,
+                _ => {
+                    return failure(ServerError::DataGuardError {
+                        name: String::from("Connection"),
+                    })
+                }
+            };
+            if let Err(error) = Quota::check_user(user.id, 0, &connection) {
+                return failure(error);
+            }
+        }
+
         let form = match MultipartForm::from_request(request, data, &user_spool(user.id)) {
             Ok(success) => success,
             Err(error) => return failure(error),
@@ -46,6 +69,20 @@ impl FromDataSimple for FileUpload {
             Ok(success) => success,
             Err(error) => return failure(error),
         };
+        let password = match form.get_text_opt("password") {
+            Ok(success) => success
+                .filter(|password| !password.is_empty())
+                .map(Secret::from),
+            Err(error) => return failure(error),
+        };
+        let max_downloads = match form.get_text_opt("max_downloads") {
+            Ok(success) => success,
+            Err(error) => return failure(error),
+        };
+        let burn = match form.get_text_opt("burn") {
+            Ok(success) => success,
+            Err(error) => return failure(error),
+        };
         let (file, size) = match form.get_file("file") {
             Ok(success) => success,
             Err(error) => return failure(error),
@@ -55,6 +92,9 @@ impl FromDataSimple for FileUpload {
             form: form,
             name: name,
             meta: meta,
+            password: password,
+            max_downloads: max_downloads,
+            burn: burn,
             file: file,
             size: size,
         };